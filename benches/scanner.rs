@@ -0,0 +1,46 @@
+//! Scans generated programs of doubling size and reports the time per byte.
+//! A linear scanner keeps that figure roughly flat as the input grows.
+//!
+//! Run with `cargo bench --bench scanner`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rlox::scanner::Scanner;
+
+const SNIPPET: &str = r#"// Ünïcödé in a comment.
+fun fib(n) {
+  if (n <= 1) return n;
+  return fib(n - 2) + fib(n - 1);
+}
+var greeting = "héllo, wörld";
+for (var i = 0; i < 20; i = i + 1) {
+  print fib(i) * 31 / 7 != greeting;
+}
+"#;
+
+const RUNS: u32 = 5;
+
+fn main() {
+    println!("{:>10} {:>12} {:>12}", "bytes", "best", "ns/byte");
+    let mut repeats = 256;
+    while repeats <= 8192 {
+        let source = SNIPPET.repeat(repeats);
+        let best = (0..RUNS).map(|_| time_scan(&source)).min().unwrap();
+        println!(
+            "{:>10} {:>12.2?} {:>12.2}",
+            source.len(),
+            best,
+            best.as_nanos() as f64 / source.len() as f64
+        );
+        repeats *= 2;
+    }
+}
+
+fn time_scan(source: &str) -> Duration {
+    let start = Instant::now();
+    let tokens = Scanner::new(black_box(source)).scan_tokens();
+    let elapsed = start.elapsed();
+    black_box(tokens);
+    elapsed
+}
@@ -1,150 +1,549 @@
-use crate::{parser::{Expr, ExprVisitor, Visitor, StmtExpr}, token_type::TokenType};
+use crate::{
+    parser::{Expr, ExprVisitor, FunctionStmt, StmtExpr, Visitor},
+    token_type::TokenType,
+    value::Value,
+};
 
-pub struct AstPrinter {}
+/// The notations the syntax tree can be printed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    /// Lisp-like S-expressions, e.g. `(* (group (+ 1 2)) 3)`.
+    SExpression,
+    /// Reverse Polish notation, e.g. `1 2 + 3 *`.
+    Rpn,
+}
 
+/// Prints a whole program, one statement per line.
+pub fn print_program(statements: &[StmtExpr], notation: Notation) -> String {
+    let lines: Vec<String> = match notation {
+        Notation::SExpression => {
+            let mut printer = AstPrinter::new();
+            statements.iter().map(|s| printer.print_stmt(s)).collect()
+        }
+        Notation::Rpn => {
+            let mut printer = RpnPrinter::new();
+            statements.iter().map(|s| printer.print_stmt(s)).collect()
+        }
+    };
+    lines.join("\n")
+}
 
+/// Strings are quoted so they can't be confused with identifiers.
+fn literal(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        value => value.to_string(),
+    }
+}
 
-impl ExprVisitor<Option<String>> for AstPrinter {
-    fn visit_binary(&mut self, e: &Expr) -> Option<String> {
+/// Prints the syntax tree as S-expressions.
+pub struct AstPrinter {}
+
+impl ExprVisitor<String> for AstPrinter {
+    fn visit_binary(&mut self, e: &Expr) -> String {
         match e {
             Expr::Binary(e) => {
-                Some(self.parenthesize(&e.operator.lexeme, &[e.clone().left, e.clone().right]))
+                let parts = [self.print(&e.left), self.print(&e.right)];
+                parenthesize(&e.operator.lexeme, &parts)
             }
-            _ => None,
+            _ => String::new(),
         }
     }
 
-    fn visit_grouping(&mut self, e: &Expr) -> Option<String> {
+    fn visit_grouping(&mut self, e: &Expr) -> String {
         match e {
-            Expr::Grouping(e) => Some(self.parenthesize("group", &[e.clone().expression])),
-            _ => None,
+            Expr::Grouping(e) => parenthesize("group", &[self.print(&e.expression)]),
+            _ => String::new(),
         }
     }
 
-    fn visit_literal(&mut self, e: &Expr) -> Option<String> {
+    fn visit_literal(&mut self, e: &Expr) -> String {
         match e {
-            Expr::Literal(e) => {
-                if e.value == None {
-                    return Some("nil".to_owned());
-                }
-                Some(e.clone().value.unwrap())
-            }
-            _ => None,
+            Expr::Literal(e) => literal(&e.value),
+            _ => String::new(),
         }
     }
 
-    fn visit_unary(&mut self, e: &Expr) -> Option<String> {
+    fn visit_unary(&mut self, e: &Expr) -> String {
         match e {
-            Expr::Unary(e) => Some(self.parenthesize(&e.operator.lexeme, &[e.clone().right])),
-            _ => None
+            Expr::Unary(e) => parenthesize(&e.operator.lexeme, &[self.print(&e.right)]),
+            _ => String::new(),
         }
     }
 
-    
+    fn visit_print(&mut self, e: &StmtExpr) -> String {
+        parenthesize("print", &[self.print(&e.get_inner())])
+    }
 
-    fn visit_print(&mut self, e: &StmtExpr) -> Option<String> {
-        None
+    fn visit_expression(&mut self, e: &StmtExpr) -> String {
+        parenthesize(";", &[self.print(&e.get_inner())])
     }
 
-    fn visit_expression(&mut self, e: &StmtExpr) -> Option<String> {
-        None
+    fn visit_var(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Var {
+                name, initializer, ..
+            } => {
+                let mut parts = vec![name.lexeme.clone()];
+                parts.extend(initializer.iter().map(|i| self.print(i)));
+                parenthesize("var", &parts)
+            }
+            _ => String::new(),
+        }
     }
 
-    fn visit_var(&mut self, e: &crate::parser::VariableExpr) -> Option<String> {
-        None
+    fn visit_block(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Block { statements, .. } => {
+                let parts: Vec<String> = statements.iter().map(|s| self.print_stmt(s)).collect();
+                parenthesize("block", &parts)
+            }
+            _ => String::new(),
+        }
     }
-}
 
-// impl Visitor<Option<String>> for AstPrinter {
+    fn visit_variable(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Variable(e) => e.name.lexeme.clone(),
+            _ => String::new(),
+        }
+    }
 
-// }
+    fn visit_assign(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Assign(e) => parenthesize("=", &[e.name.lexeme.clone(), self.print(&e.value)]),
+            _ => String::new(),
+        }
+    }
 
-pub fn ac(e: &Expr) -> String {
-    let mut stri = String::new();
-    match e {
-        Expr::Binary(a) => {
-            match a.clone().right {
-                Expr::Literal(l) => {
-                    stri.push_str(&format!(" {}", &l.value.unwrap()));
-                },
-                Expr::Unary(a) => {
-                    ac(&Expr::Unary(a));
-                }
-                Expr::Binary(a) => {
-                    ac(&Expr::Binary(a));
-
-                },
-                Expr::Grouping(a) => {
-                    ac(&Expr::Grouping(a));
-                },
-                Expr::Stmt(_) => todo!(), 
-                Expr::Variable(_) => todo!("Variable"),
-                
-            }
-
-            match a.clone().left {
-                Expr::Literal(l) => {
-                    stri.push_str(&l.value.unwrap());
-                },
-                Expr::Unary(a) => {
-                    ac(&Expr::Unary(a));
+    fn visit_if(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let mut parts = vec![self.print(condition), self.print_stmt(then_branch)];
+                match else_branch {
+                    Some(else_branch) => {
+                        parts.push(self.print_stmt(else_branch));
+                        parenthesize("if-else", &parts)
+                    }
+                    None => parenthesize("if", &parts),
                 }
-                Expr::Binary(a) => {
-                    ac(&Expr::Binary(a));
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_while(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::While {
+                condition, body, ..
+            } => parenthesize("while", &[self.print(condition), self.print_stmt(body)]),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_call(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Call(e) => {
+                let mut parts = vec![self.print(&e.callee)];
+                parts.extend(e.arguments.iter().map(|a| self.print(a)));
+                parenthesize("call", &parts)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_function(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Function(function) => self.function(function),
+            _ => String::new(),
+        }
+    }
 
-                },
-                Expr::Grouping(a) => {
-                    ac(&Expr::Grouping(a));
-                },
-                Expr::Stmt(_) => todo!(), 
-                Expr::Variable(_) => todo!("Variable"),
-                
+    fn visit_return(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Return { value, .. } => {
+                let parts: Vec<String> = value.iter().map(|v| self.print(v)).collect();
+                parenthesize("return", &parts)
             }
+            _ => String::new(),
+        }
+    }
 
-            match a.operator.tty {
-                TokenType::Plus => {
-                    stri.push_str(" + ")
+    fn visit_class(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let mut parts = vec![name.lexeme.clone()];
+                if let Some(superclass) = superclass {
+                    parts.push(format!("< {}", superclass.name.lexeme));
                 }
-                _ => ()
+                parts.extend(methods.iter().map(|m| self.function(m)));
+                parenthesize("class", &parts)
             }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_get(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Get(e) => parenthesize(".", &[self.print(&e.object), e.name.lexeme.clone()]),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_set(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Set(e) => {
+                let parts = [
+                    self.print(&e.object),
+                    e.name.lexeme.clone(),
+                    self.print(&e.value),
+                ];
+                parenthesize("set", &parts)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_this(&mut self, _e: &Expr) -> String {
+        "this".to_string()
+    }
+
+    fn visit_super(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Super(e) => format!("(super {})", e.method.lexeme),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_logical(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Logical(e) => {
+                let parts = [self.print(&e.left), self.print(&e.right)];
+                parenthesize(&e.operator.lexeme, &parts)
+            }
+            _ => String::new(),
         }
-        _ => ()
-        // Expr::Grouping(a) => todo!(),
-        // Expr::Literal(a) => todo!(),
-        // Expr::Unary(a) => todo!(),
     }
-    stri
 }
+
+impl Default for AstPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AstPrinter {
     pub fn new() -> Self {
         AstPrinter {}
     }
-   
-    pub fn print(&mut self, expr: &Expr) -> Option<String> {
-        match expr {
-            Expr::Binary(e) => e.accept(self),
-            Expr::Grouping(e) => e.accept(self),
-            Expr::Literal(e) => e.accept(self),
-            Expr::Unary(e) => e.accept(self),
-            Expr::Stmt(e) => e.accept(self),
-            Expr::Variable(e) => e.accept(self),
-        }
-    }
-    fn parenthesize(&mut self, name: &str, exprs: &[Expr]) -> String {
-        let mut builder = String::new();
-        builder.push('(');
-        builder.push_str(name);
-        for expr in exprs {
-            println!("{:#?}", &expr);
-            builder.push(' ');
-            match &expr.accept(self) {
-                None => continue,
-                Some(v) => {
-                    builder.push_str(v);
-                } 
-            }
-        }
-        builder.push(')');
-        builder
+
+    pub fn print(&mut self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    pub fn print_stmt(&mut self, stmt: &StmtExpr) -> String {
+        stmt.accept(self)
+    }
+
+    fn function(&mut self, function: &FunctionStmt) -> String {
+        let params: Vec<&str> = function.params.iter().map(|p| p.lexeme.as_str()).collect();
+        let mut parts = vec![function.name.lexeme.clone(), format!("({})", params.join(" "))];
+        parts.extend(function.body.iter().map(|s| self.print_stmt(s)));
+        parenthesize("fun", &parts)
+    }
+}
+
+fn parenthesize(name: &str, parts: &[String]) -> String {
+    let mut builder = String::new();
+    builder.push('(');
+    builder.push_str(name);
+    for part in parts {
+        builder.push(' ');
+        builder.push_str(part);
+    }
+    builder.push(')');
+    builder
+}
+
+/// Prints the syntax tree in reverse Polish notation: operands first, then
+/// the operator. Grouping disappears, unary minus prints as `~` to tell it
+/// apart from subtraction, and operators taking a variable number of
+/// operands carry the count, as in `f 1 2 call/2`.
+pub struct RpnPrinter {}
+
+impl ExprVisitor<String> for RpnPrinter {
+    fn visit_binary(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Binary(e) => {
+                let parts = [self.print(&e.left), self.print(&e.right)];
+                postfix(&parts, &e.operator.lexeme)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_grouping(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Grouping(e) => self.print(&e.expression),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_literal(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Literal(e) => literal(&e.value),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_unary(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Unary(e) => {
+                let operator = match e.operator.tty {
+                    TokenType::Minus => "~",
+                    _ => &e.operator.lexeme,
+                };
+                postfix(&[self.print(&e.right)], operator)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_print(&mut self, e: &StmtExpr) -> String {
+        postfix(&[self.print(&e.get_inner())], "print")
+    }
+
+    fn visit_expression(&mut self, e: &StmtExpr) -> String {
+        postfix(&[self.print(&e.get_inner())], ";")
+    }
+
+    fn visit_var(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Var {
+                name, initializer, ..
+            } => {
+                let mut parts: Vec<String> = initializer.iter().map(|i| self.print(i)).collect();
+                parts.push(name.lexeme.clone());
+                postfix(&parts, &format!("var/{}", parts.len()))
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_block(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Block { statements, .. } => {
+                let parts: Vec<String> = statements.iter().map(|s| self.print_stmt(s)).collect();
+                postfix(&parts, &format!("block/{}", parts.len()))
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_variable(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Variable(e) => e.name.lexeme.clone(),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_assign(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Assign(e) => postfix(&[self.print(&e.value), e.name.lexeme.clone()], "="),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_if(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let mut parts = vec![self.print(condition), self.print_stmt(then_branch)];
+                match else_branch {
+                    Some(else_branch) => {
+                        parts.push(self.print_stmt(else_branch));
+                        postfix(&parts, "if-else")
+                    }
+                    None => postfix(&parts, "if"),
+                }
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_while(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::While {
+                condition, body, ..
+            } => postfix(&[self.print(condition), self.print_stmt(body)], "while"),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_call(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Call(e) => {
+                let mut parts = vec![self.print(&e.callee)];
+                parts.extend(e.arguments.iter().map(|a| self.print(a)));
+                postfix(&parts, &format!("call/{}", e.arguments.len()))
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_function(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Function(function) => self.function(function),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_return(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Return { value, .. } => {
+                let parts: Vec<String> = value.iter().map(|v| self.print(v)).collect();
+                postfix(&parts, &format!("return/{}", parts.len()))
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_class(&mut self, e: &StmtExpr) -> String {
+        match e {
+            StmtExpr::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let mut parts: Vec<String> = methods.iter().map(|m| self.function(m)).collect();
+                let operator = match superclass {
+                    Some(superclass) => {
+                        parts.push(superclass.name.lexeme.clone());
+                        format!("subclass/{}", methods.len())
+                    }
+                    None => format!("class/{}", methods.len()),
+                };
+                parts.push(name.lexeme.clone());
+                postfix(&parts, &operator)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_get(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Get(e) => postfix(&[self.print(&e.object), e.name.lexeme.clone()], "."),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_set(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Set(e) => {
+                let parts = [
+                    self.print(&e.object),
+                    self.print(&e.value),
+                    e.name.lexeme.clone(),
+                ];
+                postfix(&parts, ".=")
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn visit_this(&mut self, _e: &Expr) -> String {
+        "this".to_string()
+    }
+
+    fn visit_super(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Super(e) => format!("{} super", e.method.lexeme),
+            _ => String::new(),
+        }
+    }
+
+    fn visit_logical(&mut self, e: &Expr) -> String {
+        match e {
+            Expr::Logical(e) => {
+                let parts = [self.print(&e.left), self.print(&e.right)];
+                postfix(&parts, &e.operator.lexeme)
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+impl Default for RpnPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RpnPrinter {
+    pub fn new() -> Self {
+        RpnPrinter {}
+    }
+
+    pub fn print(&mut self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    pub fn print_stmt(&mut self, stmt: &StmtExpr) -> String {
+        stmt.accept(self)
+    }
+
+    /// The body, then the name and parameters, then `fun/N` where N is the
+    /// number of statements in the body.
+    fn function(&mut self, function: &FunctionStmt) -> String {
+        let params: Vec<&str> = function.params.iter().map(|p| p.lexeme.as_str()).collect();
+        let mut parts: Vec<String> = function.body.iter().map(|s| self.print_stmt(s)).collect();
+        parts.push(format!("{}({})", function.name.lexeme, params.join(" ")));
+        postfix(&parts, &format!("fun/{}", function.body.len()))
+    }
+}
+
+fn postfix(parts: &[String], operator: &str) -> String {
+    let mut builder = String::new();
+    for part in parts {
+        builder.push_str(part);
+        builder.push(' ');
+    }
+    builder.push_str(operator);
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn rpn(source: &str) -> String {
+        let (statements, errors) = Parser::new(Scanner::new(source).scan_tokens()).parse();
+        assert!(errors.is_empty());
+        print_program(&statements, Notation::Rpn)
+    }
+
+    #[test]
+    fn rpn_counts_optional_operands() {
+        assert_eq!(rpn("var x; var y = x;"), "x var/1\nx y var/2");
+        assert_eq!(
+            rpn("fun f() { return; } fun g(a) { return a; }"),
+            "return/0 f() fun/1\na return/1 g(a) fun/1"
+        );
     }
 }
@@ -0,0 +1,98 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    env::Environment,
+    errors::RuntimeError,
+    interpreter::{Interpreter, RuntimeResult},
+    parser::FunctionStmt,
+    token::Token,
+    value::Value,
+};
+
+/// Deepest nesting of Lox calls before a program fails with "Stack
+/// overflow.", on either backend. The tree-walker uses several kilobytes of
+/// Rust stack per Lox call in debug builds, so this stays low enough for a
+/// default 2 MB thread. clox uses the same limit.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// Anything that can appear on the left of a call expression.
+pub trait LoxCallable: fmt::Debug + fmt::Display {
+    fn arity(&self) -> usize;
+    /// `paren` is the call site's closing parenthesis, used to locate errors.
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> RuntimeResult;
+}
+
+/// A user-defined function together with the environment it was declared in.
+#[derive(Debug)]
+pub struct LoxFunction {
+    declaration: Rc<FunctionStmt>,
+    closure: Rc<RefCell<Environment>>,
+    /// Initializers always return `this`, even on a bare `return;`.
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        declaration: Rc<FunctionStmt>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    /// Returns a copy of this method whose closure has `this` bound to
+    /// `instance`.
+    pub fn bind(&self, instance: Value) -> LoxFunction {
+        let mut environment = Environment::with_enclosing(self.closure.clone());
+        environment.define("this".to_string(), instance);
+        LoxFunction::new(
+            self.declaration.clone(),
+            Rc::new(RefCell::new(environment)),
+            self.is_initializer,
+        )
+    }
+
+    fn bound_this(&self) -> Value {
+        self.closure.borrow().get_own("this").unwrap_or(Value::Nil)
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        _paren: &Token,
+        arguments: Vec<Value>,
+    ) -> RuntimeResult {
+        let mut environment = Environment::with_enclosing(self.closure.clone());
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            environment.define(param.lexeme.clone(), argument);
+        }
+        match interpreter.execute_block(&self.declaration.body, environment) {
+            Ok(()) if self.is_initializer => Ok(self.bound_this()),
+            Ok(()) => Ok(Value::Nil),
+            Err(RuntimeError::Return(_)) if self.is_initializer => Ok(self.bound_this()),
+            Err(RuntimeError::Return(value)) => Ok(value),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
+    }
+}
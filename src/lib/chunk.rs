@@ -0,0 +1,159 @@
+use std::fmt;
+
+use crate::{token::Span, value::Value};
+
+/// One VM instruction. Operands are stored inline: `u32` operands index the
+/// chunk's constant pool, a stack slot or an upvalue, or give a jump
+/// distance in instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCode {
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u32),
+    SetLocal(u32),
+    /// Global operands name the variable by its constant.
+    GetGlobal(u32),
+    DefineGlobal(u32),
+    SetGlobal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    GetProperty(u32),
+    /// Fails unless the top of the stack is an instance. Runs before the
+    /// value of a property assignment is evaluated, as in the interpreter.
+    ExpectInstance,
+    SetProperty(u32),
+    GetSuper(u32),
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// Jumps forward by its operand.
+    Jump(u32),
+    /// Jumps forward if the top of the stack is falsey, without popping it.
+    JumpIfFalse(u32),
+    /// Jumps backward by its operand.
+    Loop(u32),
+    /// Calls the value below its argument count of arguments.
+    Call(u8),
+    /// Makes a closure of the function constant, capturing the upvalues it
+    /// lists.
+    Closure(u32),
+    CloseUpvalue,
+    Return,
+    Class(u32),
+    /// Like `Class`, but pops the superclass and starts with its methods.
+    Subclass(u32),
+    Method(u32),
+}
+
+/// A compiled function body: its instructions, the constants they refer
+/// to, and where in the source each instruction came from.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Value>,
+    /// Run-length encoded: the span of every instruction from the given
+    /// index until the next entry.
+    spans: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an instruction and returns its index.
+    pub fn write(&mut self, op: OpCode, span: Span) -> usize {
+        let index = self.code.len();
+        if self.spans.last().map(|(_, last)| *last) != Some(span) {
+            self.spans.push((index, span));
+        }
+        self.code.push(op);
+        index
+    }
+
+    /// Adds a constant and returns its index.
+    pub fn add_constant(&mut self, value: Value) -> u32 {
+        self.constants.push(value);
+        u32::try_from(self.constants.len() - 1).expect("too many constants in one chunk")
+    }
+
+    /// The source span of the instruction at `index`.
+    pub fn span_at(&self, index: usize) -> Span {
+        let entry = self.spans.partition_point(|(start, _)| *start <= index);
+        entry
+            .checked_sub(1)
+            .map_or_else(Span::default, |entry| self.spans[entry].1)
+    }
+
+    /// The line of the instruction at `index`.
+    pub fn line_at(&self, index: usize) -> u64 {
+        self.span_at(index).line
+    }
+}
+
+/// Lists each instruction with its line, e.g. for debugging the compiler.
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, op) in self.code.iter().enumerate() {
+            write!(f, "{:04} {:>4} {:?}", index, self.line_at(index), op)?;
+            match op {
+                OpCode::Constant(constant)
+                | OpCode::GetGlobal(constant)
+                | OpCode::DefineGlobal(constant)
+                | OpCode::SetGlobal(constant)
+                | OpCode::GetProperty(constant)
+                | OpCode::SetProperty(constant)
+                | OpCode::GetSuper(constant)
+                | OpCode::Closure(constant)
+                | OpCode::Class(constant)
+                | OpCode::Subclass(constant)
+                | OpCode::Method(constant) => {
+                    writeln!(f, " {}", self.constants[*constant as usize])?
+                }
+                _ => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a closure captures a local of the enclosing function, or one of
+/// the enclosing function's own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpvalueRef {
+    pub is_local: bool,
+    pub index: u32,
+}
+
+/// A function as compiled, before it closes over any variables.
+#[derive(Debug, Default)]
+pub struct Function {
+    /// Empty for the top-level script.
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueRef>,
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
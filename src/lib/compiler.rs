@@ -0,0 +1,581 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    chunk::{Function, OpCode, UpvalueRef},
+    parser::{Expr, ExprVisitor, FunctionStmt, StmtExpr, Visitor},
+    token::{Span, Token},
+    token_type::TokenType,
+    value::Value,
+    vm::Object,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// A local variable and the stack slot it lives in, which is its index in
+/// [`FunctionState::locals`].
+#[derive(Debug)]
+struct Local {
+    name: String,
+    depth: usize,
+    /// Closed over by a nested function, so it must move off the stack when
+    /// it goes out of scope.
+    captured: bool,
+}
+
+/// The function currently being compiled.
+#[derive(Debug)]
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    /// Constant indices of names and strings already in the pool.
+    strings: HashMap<String, u32>,
+}
+
+impl FunctionState {
+    fn new(name: &str, kind: FunctionKind) -> Self {
+        // Slot 0 holds the function being called, or the receiver in methods.
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        Self {
+            function: Function {
+                name: name.to_string(),
+                ..Function::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: receiver.to_string(),
+                depth: 0,
+                captured: false,
+            }],
+            scope_depth: 0,
+            strings: HashMap::new(),
+        }
+    }
+}
+
+/// Compiles resolved statements into bytecode for the [`Vm`](crate::vm::Vm)
+/// in a single walk over the tree. Static errors are the resolver's job, so
+/// by the time code reaches here it always compiles.
+pub struct Compiler {
+    /// The function being compiled and those enclosing it, innermost last.
+    functions: Vec<FunctionState>,
+}
+
+impl ExprVisitor<()> for Compiler {
+    fn visit_binary(&mut self, e: &Expr) {
+        if let Expr::Binary(b) = e {
+            self.expr(&b.left);
+            self.expr(&b.right);
+            let op = match b.operator.tty {
+                TokenType::Plus => OpCode::Add,
+                TokenType::Minus => OpCode::Subtract,
+                TokenType::Star => OpCode::Multiply,
+                TokenType::Slash => OpCode::Divide,
+                TokenType::Greater => OpCode::Greater,
+                TokenType::GreaterEqual => OpCode::GreaterEqual,
+                TokenType::Less => OpCode::Less,
+                TokenType::LessEqual => OpCode::LessEqual,
+                TokenType::EqualEqual => OpCode::Equal,
+                TokenType::BangEqual => OpCode::NotEqual,
+                _ => unreachable!("parser only builds binary expressions from binary operators"),
+            };
+            self.emit(op, b.operator.span);
+        }
+    }
+
+    fn visit_grouping(&mut self, e: &Expr) {
+        if let Expr::Grouping(g) = e {
+            self.expr(&g.expression);
+        }
+    }
+
+    fn visit_literal(&mut self, e: &Expr) {
+        if let Expr::Literal(l) = e {
+            match &l.value {
+                Value::Nil => self.emit(OpCode::Nil, l.span),
+                Value::Bool(true) => self.emit(OpCode::True, l.span),
+                Value::Bool(false) => self.emit(OpCode::False, l.span),
+                Value::Number(n) => {
+                    let constant = self.constant(Value::Number(*n));
+                    self.emit(OpCode::Constant(constant), l.span)
+                }
+                Value::String(s) => {
+                    let constant = self.string_constant(s);
+                    self.emit(OpCode::Constant(constant), l.span)
+                }
+                _ => unreachable!("literals are never callables, classes or instances"),
+            };
+        }
+    }
+
+    fn visit_unary(&mut self, e: &Expr) {
+        if let Expr::Unary(u) = e {
+            self.expr(&u.right);
+            let op = match u.operator.tty {
+                TokenType::Minus => OpCode::Negate,
+                _ => OpCode::Not,
+            };
+            self.emit(op, u.operator.span);
+        }
+    }
+
+    fn visit_print(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Print { expression, span } = e {
+            self.expr(expression);
+            self.emit(OpCode::Print, *span);
+        }
+    }
+
+    fn visit_expression(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Expression { expression, span } = e {
+            self.expr(expression);
+            self.emit(OpCode::Pop, *span);
+        }
+    }
+
+    fn visit_var(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Var {
+            name,
+            initializer,
+            span,
+        } = e
+        {
+            match initializer {
+                Some(initializer) => self.expr(initializer),
+                None => {
+                    self.emit(OpCode::Nil, *span);
+                }
+            }
+            self.define_variable(name);
+        }
+    }
+
+    fn visit_block(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Block { statements, span } = e {
+            self.begin_scope();
+            for statement in statements {
+                statement.accept(self);
+            }
+            self.end_scope(*span);
+        }
+    }
+
+    fn visit_variable(&mut self, e: &Expr) {
+        if let Expr::Variable(v) = e {
+            self.get_variable(&v.name.lexeme, v.name.span);
+        }
+    }
+
+    fn visit_assign(&mut self, e: &Expr) {
+        if let Expr::Assign(a) = e {
+            self.expr(&a.value);
+            let op = match self.resolve(&a.name.lexeme) {
+                Resolved::Local(slot) => OpCode::SetLocal(slot),
+                Resolved::Upvalue(index) => OpCode::SetUpvalue(index),
+                Resolved::Global => OpCode::SetGlobal(self.string_constant(&a.name.lexeme)),
+            };
+            self.emit(op, a.name.span);
+        }
+    }
+
+    fn visit_if(&mut self, e: &StmtExpr) {
+        if let StmtExpr::If {
+            condition,
+            then_branch,
+            else_branch,
+            span,
+        } = e
+        {
+            self.expr(condition);
+            let then_jump = self.emit(OpCode::JumpIfFalse(0), *span);
+            self.emit(OpCode::Pop, *span);
+            then_branch.accept(self);
+            let else_jump = self.emit(OpCode::Jump(0), *span);
+            self.patch_jump(then_jump);
+            self.emit(OpCode::Pop, *span);
+            if let Some(else_branch) = else_branch {
+                else_branch.accept(self);
+            }
+            self.patch_jump(else_jump);
+        }
+    }
+
+    fn visit_while(&mut self, e: &StmtExpr) {
+        if let StmtExpr::While {
+            condition,
+            body,
+            span,
+        } = e
+        {
+            let loop_start = self.current().function.chunk.code.len();
+            self.expr(condition);
+            let exit_jump = self.emit(OpCode::JumpIfFalse(0), *span);
+            self.emit(OpCode::Pop, *span);
+            body.accept(self);
+            self.emit_loop(loop_start, *span);
+            self.patch_jump(exit_jump);
+            self.emit(OpCode::Pop, *span);
+        }
+    }
+
+    fn visit_logical(&mut self, e: &Expr) {
+        if let Expr::Logical(l) = e {
+            self.expr(&l.left);
+            // Short-circuit: the deciding operand is left as the result.
+            let end_jump = if l.operator.tty == TokenType::Or {
+                let else_jump = self.emit(OpCode::JumpIfFalse(0), l.span);
+                let end_jump = self.emit(OpCode::Jump(0), l.span);
+                self.patch_jump(else_jump);
+                end_jump
+            } else {
+                self.emit(OpCode::JumpIfFalse(0), l.span)
+            };
+            self.emit(OpCode::Pop, l.span);
+            self.expr(&l.right);
+            self.patch_jump(end_jump);
+        }
+    }
+
+    fn visit_call(&mut self, e: &Expr) {
+        if let Expr::Call(c) = e {
+            self.expr(&c.callee);
+            for argument in &c.arguments {
+                self.expr(argument);
+            }
+            // The parser rejects calls with more arguments than this.
+            let count = u8::try_from(c.arguments.len()).unwrap_or(u8::MAX);
+            self.emit(OpCode::Call(count), c.paren.span);
+        }
+    }
+
+    fn visit_function(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Function(function) = e {
+            // Declare the name first so the body can call itself.
+            if self.current().scope_depth > 0 {
+                self.add_local(&function.name.lexeme);
+            }
+            self.function(function, FunctionKind::Function);
+            if self.current().scope_depth == 0 {
+                let name = self.string_constant(&function.name.lexeme);
+                self.emit(OpCode::DefineGlobal(name), function.name.span);
+            }
+        }
+    }
+
+    fn visit_return(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Return { value, span, .. } = e {
+            match value {
+                Some(value) => {
+                    self.expr(value);
+                    self.emit(OpCode::Return, *span);
+                }
+                None => self.emit_return(*span),
+            }
+        }
+    }
+
+    fn visit_class(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Class {
+            name,
+            superclass,
+            methods,
+            span,
+        } = e
+        {
+            let name_constant = self.string_constant(&name.lexeme);
+            match superclass {
+                Some(superclass) => {
+                    self.get_variable(&superclass.name.lexeme, superclass.name.span);
+                    self.emit(OpCode::Subclass(name_constant), superclass.name.span);
+                }
+                None => {
+                    self.emit(OpCode::Class(name_constant), name.span);
+                }
+            }
+            self.define_variable(name);
+
+            // The superclass goes in a local named `super` around the
+            // methods, so they capture it as an upvalue.
+            if let Some(superclass) = superclass {
+                self.begin_scope();
+                self.get_variable(&superclass.name.lexeme, superclass.name.span);
+                self.add_local("super");
+            }
+
+            self.get_variable(&name.lexeme, name.span);
+            for method in methods {
+                let kind = if method.name.lexeme == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                self.function(method, kind);
+                let method_name = self.string_constant(&method.name.lexeme);
+                self.emit(OpCode::Method(method_name), method.name.span);
+            }
+            self.emit(OpCode::Pop, *span);
+
+            if superclass.is_some() {
+                self.end_scope(*span);
+            }
+        }
+    }
+
+    fn visit_get(&mut self, e: &Expr) {
+        if let Expr::Get(g) = e {
+            self.expr(&g.object);
+            let name = self.string_constant(&g.name.lexeme);
+            self.emit(OpCode::GetProperty(name), g.name.span);
+        }
+    }
+
+    fn visit_set(&mut self, e: &Expr) {
+        if let Expr::Set(s) = e {
+            self.expr(&s.object);
+            self.emit(OpCode::ExpectInstance, s.name.span);
+            self.expr(&s.value);
+            let name = self.string_constant(&s.name.lexeme);
+            self.emit(OpCode::SetProperty(name), s.name.span);
+        }
+    }
+
+    fn visit_this(&mut self, e: &Expr) {
+        if let Expr::This(t) = e {
+            self.get_variable("this", t.keyword.span);
+        }
+    }
+
+    fn visit_super(&mut self, e: &Expr) {
+        if let Expr::Super(s) = e {
+            self.get_variable("this", s.keyword.span);
+            self.get_variable("super", s.keyword.span);
+            let method = self.string_constant(&s.method.lexeme);
+            self.emit(OpCode::GetSuper(method), s.method.span);
+        }
+    }
+}
+
+/// Where a name refers to, from the function being compiled.
+enum Resolved {
+    Local(u32),
+    Upvalue(u32),
+    Global,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            functions: Vec::new(),
+        }
+    }
+
+    /// Compiles a whole program into the function the VM runs first.
+    pub fn compile(&mut self, statements: &[StmtExpr]) -> Rc<Function> {
+        self.functions
+            .push(FunctionState::new("", FunctionKind::Script));
+        for statement in statements {
+            statement.accept(self);
+        }
+        let end = statements.last().map_or_else(Span::default, StmtExpr::span);
+        self.emit_return(end);
+        let script = self.functions.pop().expect("script is always compiling");
+        Rc::new(script.function)
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    /// Compiles `declaration` as a new function and emits the closure that
+    /// creates it at runtime.
+    fn function(&mut self, declaration: &FunctionStmt, kind: FunctionKind) {
+        let mut state = FunctionState::new(&declaration.name.lexeme, kind);
+        state.function.arity = declaration.params.len();
+        self.functions.push(state);
+        self.begin_scope();
+        for param in &declaration.params {
+            self.add_local(&param.lexeme);
+        }
+        for statement in &declaration.body {
+            statement.accept(self);
+        }
+        self.emit_return(declaration.span);
+        // No need to end the scope: returning discards the whole frame.
+        let state = self.functions.pop().expect("function is compiling");
+        let constant = self.constant(Value::Vm(Object::Function(Rc::new(state.function))));
+        self.emit(OpCode::Closure(constant), declaration.name.span);
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("compiler has a current function")
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) -> usize {
+        self.current().function.chunk.write(op, span)
+    }
+
+    /// Returns from the current function. Initializers always return `this`.
+    fn emit_return(&mut self, span: Span) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0), span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+        self.emit(OpCode::Return, span);
+    }
+
+    /// Points the jump at `index` to the next instruction emitted.
+    fn patch_jump(&mut self, index: usize) {
+        let code = &mut self.current().function.chunk.code;
+        let distance = jump_distance(code.len() - index - 1);
+        code[index] = match code[index] {
+            OpCode::Jump(_) => OpCode::Jump(distance),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(distance),
+            op => unreachable!("patching {:?}, which is not a jump", op),
+        };
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, span: Span) {
+        let distance = jump_distance(self.current().function.chunk.code.len() - loop_start + 1);
+        self.emit(OpCode::Loop(distance), span);
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.current().function.chunk.add_constant(value)
+    }
+
+    /// Adds a string to the pool once per function, however often it is used.
+    fn string_constant(&mut self, string: &str) -> u32 {
+        if let Some(constant) = self.current().strings.get(string) {
+            return *constant;
+        }
+        let constant = self.constant(Value::String(string.to_string()));
+        self.current().strings.insert(string.to_string(), constant);
+        constant
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    /// Discards the locals of the innermost scope, moving any that were
+    /// captured into their upvalues.
+    fn end_scope(&mut self, span: Span) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while let Some(local) = self.current().locals.pop_if(|local| local.depth > depth) {
+            let op = if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit(op, span);
+        }
+    }
+
+    /// Binds `name` to the value on top of the stack: a new local slot inside
+    /// a scope, a global otherwise.
+    fn define_variable(&mut self, name: &Token) {
+        if self.current().scope_depth > 0 {
+            self.add_local(&name.lexeme);
+        } else {
+            let constant = self.string_constant(&name.lexeme);
+            self.emit(OpCode::DefineGlobal(constant), name.span);
+        }
+    }
+
+    fn add_local(&mut self, name: &str) {
+        let state = self.current();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: name.to_string(),
+            depth,
+            captured: false,
+        });
+    }
+
+    fn get_variable(&mut self, name: &str, span: Span) {
+        let op = match self.resolve(name) {
+            Resolved::Local(slot) => OpCode::GetLocal(slot),
+            Resolved::Upvalue(index) => OpCode::GetUpvalue(index),
+            Resolved::Global => OpCode::GetGlobal(self.string_constant(name)),
+        };
+        self.emit(op, span);
+    }
+
+    fn resolve(&mut self, name: &str) -> Resolved {
+        let innermost = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(innermost, name) {
+            return Resolved::Local(slot);
+        }
+        match self.resolve_upvalue(innermost, name) {
+            Some(index) => Resolved::Upvalue(index),
+            None => Resolved::Global,
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u32> {
+        let locals = &self.functions[function].locals;
+        let slot = locals.iter().rposition(|local| local.name == name)?;
+        Some(u32::try_from(slot).expect("too many locals in one function"))
+    }
+
+    /// Finds `name` in an enclosing function, threading an upvalue through
+    /// every function in between.
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u32> {
+        let enclosing = function.checked_sub(1)?;
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[slot as usize].captured = true;
+            return Some(self.add_upvalue(
+                function,
+                UpvalueRef {
+                    is_local: true,
+                    index: slot,
+                },
+            ));
+        }
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(
+            function,
+            UpvalueRef {
+                is_local: false,
+                index,
+            },
+        ))
+    }
+
+    fn add_upvalue(&mut self, function: usize, upvalue: UpvalueRef) -> u32 {
+        let upvalues = &mut self.functions[function].function.upvalues;
+        let index = match upvalues.iter().position(|existing| *existing == upvalue) {
+            Some(index) => index,
+            None => {
+                upvalues.push(upvalue);
+                upvalues.len() - 1
+            }
+        };
+        u32::try_from(index).expect("too many upvalues in one function")
+    }
+}
+
+fn jump_distance(distance: usize) -> u32 {
+    u32::try_from(distance).expect("jump too long")
+}
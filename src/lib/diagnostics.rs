@@ -0,0 +1,312 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+use crate::{
+    errors::{ParseError, RuntimeError, VmError},
+    token::Span,
+    token_type::TokenType,
+};
+
+/// How diagnostics are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Source snippets with carets, colored when stderr is a terminal.
+    #[default]
+    Human,
+    /// One JSON object per line, for CI annotations.
+    Json,
+}
+
+/// Receives diagnostics in place of the default stderr output.
+pub type DiagnosticHandler = dyn FnMut(&Diagnostic);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Found before the program runs: scanning, parsing or resolving.
+    Compile,
+    Runtime,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Compile => "error",
+            Kind::Runtime => "runtime error",
+        }
+    }
+}
+
+/// A message attached to a region of the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: &str) -> Self {
+        Self {
+            span,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A problem in a Lox program: a headline, the range it is about, other
+/// ranges that explain it, and free-form notes.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub kind: Kind,
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: &str, span: Span) -> Self {
+        Self {
+            kind: Kind::Compile,
+            message: message.to_string(),
+            primary: Label::new(span, ""),
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn runtime(message: &str, span: Span) -> Self {
+        Self {
+            kind: Kind::Runtime,
+            ..Self::error(message, span)
+        }
+    }
+
+    /// Sets the text shown under the primary range.
+    pub fn with_label(mut self, message: &str) -> Self {
+        self.primary.message = message.to_string();
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: &str) -> Self {
+        self.secondary.push(Label::new(span, message));
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Renders the diagnostic against `source`, which must be the text the
+    /// spans were taken from. `name` identifies that text, e.g. a file path.
+    pub fn render(&self, source: &str, name: &str, color: bool) -> String {
+        let paint = Paint(color);
+        let mut out = String::new();
+        let span = self.primary.span;
+        let _ = writeln!(
+            out,
+            "{}{}",
+            paint.error(self.kind.as_str()),
+            paint.bold(&format!(": {}", self.message))
+        );
+
+        let mut labels: Vec<(&Label, bool)> = std::iter::once((&self.primary, true))
+            .chain(self.secondary.iter().map(|label| (label, false)))
+            .collect();
+        labels.sort_by_key(|(label, _)| label.span.start);
+        let width = labels
+            .iter()
+            .map(|(label, _)| label.span.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let pad = " ".repeat(width);
+        let bar = paint.gutter("|");
+
+        let _ = writeln!(
+            out,
+            "{}{} {}:{}:{}",
+            pad,
+            paint.gutter("-->"),
+            name,
+            span.line,
+            span.column
+        );
+        let _ = writeln!(out, "{} {}", pad, bar);
+        let mut last_line = None;
+        for (label, primary) in labels {
+            let Some((text, offset, len)) = snippet(source, label.span) else {
+                continue;
+            };
+            if last_line != Some(label.span.line) {
+                let number = format!("{:>width$}", label.span.line, width = width);
+                let _ = writeln!(out, "{} {} {}", paint.gutter(&number), bar, text);
+                last_line = Some(label.span.line);
+            }
+            let (marker, style): (&str, fn(&Paint, &str) -> String) = if primary {
+                ("^", Paint::error)
+            } else {
+                ("-", Paint::gutter)
+            };
+            let mut underline = marker.repeat(len);
+            if !label.message.is_empty() {
+                underline.push(' ');
+                underline.push_str(&label.message);
+            }
+            let _ = writeln!(
+                out,
+                "{} {} {}{}",
+                pad,
+                bar,
+                " ".repeat(offset),
+                style(&paint, &underline)
+            );
+        }
+        for note in &self.notes {
+            let _ = writeln!(
+                out,
+                "{} {} {}: {}",
+                pad,
+                paint.gutter("="),
+                paint.bold("note"),
+                note
+            );
+        }
+        out
+    }
+
+    /// A single-line JSON object. The keys are `kind` ("compile" or
+    /// "runtime"), `message`, `file`, `line`, `column`, `labels` (objects with
+    /// `message`, `primary`, `start`, `end`, `line` and `column`, where
+    /// `start`/`end` are byte offsets) and `notes` (strings).
+    pub fn to_json(&self, name: &str) -> String {
+        let kind = match self.kind {
+            Kind::Compile => "compile",
+            Kind::Runtime => "runtime",
+        };
+        let labels: Vec<String> = std::iter::once(label_json(&self.primary, true))
+            .chain(self.secondary.iter().map(|label| label_json(label, false)))
+            .collect();
+        let notes: Vec<String> = self.notes.iter().map(|note| json_string(note)).collect();
+        format!(
+            "{{\"kind\":\"{}\",\"message\":{},\"file\":{},\"line\":{},\"column\":{},\"labels\":[{}],\"notes\":[{}]}}",
+            kind,
+            json_string(&self.message),
+            json_string(name),
+            self.primary.span.line,
+            self.primary.span.column,
+            labels.join(","),
+            notes.join(",")
+        )
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let token = &error.token;
+        let label = if token.tty == TokenType::Eof {
+            "at end".to_string()
+        } else {
+            format!("at '{}'", token.lexeme)
+        };
+        Diagnostic::error(&error.message, token.span).with_label(&label)
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        match error {
+            RuntimeError::Error { token, message } => Diagnostic::runtime(message, token.span),
+            RuntimeError::Return(_) => Diagnostic::runtime(&error.to_string(), Span::default()),
+        }
+    }
+}
+
+impl From<&VmError> for Diagnostic {
+    fn from(error: &VmError) -> Self {
+        Diagnostic::runtime(&error.message, error.span)
+    }
+}
+
+/// Whether diagnostics written to stderr should be colored.
+pub fn stderr_supports_color() -> bool {
+    std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// The line containing the start of `span`, with the span's offset and
+/// length within it in characters. A span running past the end of its line
+/// is cut there. Returns `None` if `span` does not fit `source`.
+fn snippet(source: &str, span: Span) -> Option<(&str, usize, usize)> {
+    let start = span.start;
+    if start > source.len() || !source.is_char_boundary(start) {
+        return None;
+    }
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let end = span.end.clamp(start, line_end);
+    if !source.is_char_boundary(end) {
+        return None;
+    }
+    let text = source[line_start..line_end].trim_end_matches('\r');
+    let offset = source[line_start..start].chars().count();
+    let len = source[start..end].chars().count().max(1);
+    Some((text, offset, len))
+}
+
+fn label_json(label: &Label, primary: bool) -> String {
+    format!(
+        "{{\"message\":{},\"primary\":{},\"start\":{},\"end\":{},\"line\":{},\"column\":{}}}",
+        json_string(&label.message),
+        primary,
+        label.span.start,
+        label.span.end,
+        label.span.line,
+        label.span.column
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Wraps text in ANSI escapes when enabled.
+struct Paint(bool);
+
+impl Paint {
+    fn style(&self, code: &str, text: &str) -> String {
+        if self.0 {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn error(&self, text: &str) -> String {
+        self.style("1;31", text)
+    }
+
+    fn gutter(&self, text: &str) -> String {
+        self.style("1;34", text)
+    }
+
+    fn bold(&self, text: &str) -> String {
+        self.style("1", text)
+    }
+}
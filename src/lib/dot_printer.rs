@@ -0,0 +1,312 @@
+use std::fmt::Write;
+
+use crate::{
+    parser::{Expr, ExprVisitor, FunctionStmt, StmtExpr, Visitor},
+    value::Value,
+};
+
+/// Renders a program as a Graphviz DOT graph.
+pub fn print_program(statements: &[StmtExpr]) -> String {
+    let mut printer = DotPrinter::new();
+    let root = printer.node("program", Shape::Statement);
+    for (index, statement) in statements.iter().enumerate() {
+        let child = statement.accept(&mut printer);
+        printer.edge(root, child, index);
+    }
+    printer.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Statement,
+    Expression,
+}
+
+/// Builds a DOT graph with one node per syntax tree node. Each visit returns
+/// the id of the node it added. Edges are numbered in child order, and the
+/// graph asks Graphviz to keep them in that order left to right.
+pub struct DotPrinter {
+    nodes: String,
+    edges: String,
+    next_id: usize,
+}
+
+impl ExprVisitor<usize> for DotPrinter {
+    fn visit_binary(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Binary(e) => self.expr(&e.operator.lexeme, &[&e.left, &e.right]),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_grouping(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Grouping(e) => self.expr("group", &[&e.expression]),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_literal(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Literal(e) => {
+                let label = match &e.value {
+                    Value::String(s) => format!("{:?}", s),
+                    value => value.to_string(),
+                };
+                self.node(&label, Shape::Expression)
+            }
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_unary(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Unary(e) => self.expr(&e.operator.lexeme, &[&e.right]),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_print(&mut self, e: &StmtExpr) -> usize {
+        self.stmt("print", &[&e.get_inner()], &[])
+    }
+
+    fn visit_expression(&mut self, e: &StmtExpr) -> usize {
+        self.stmt("expression", &[&e.get_inner()], &[])
+    }
+
+    fn visit_var(&mut self, e: &StmtExpr) -> usize {
+        match e {
+            StmtExpr::Var {
+                name, initializer, ..
+            } => {
+                let initializer: Vec<&Expr> = initializer.iter().collect();
+                self.stmt(&format!("var {}", name.lexeme), &initializer, &[])
+            }
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_block(&mut self, e: &StmtExpr) -> usize {
+        match e {
+            StmtExpr::Block { statements, .. } => {
+                let statements: Vec<&StmtExpr> = statements.iter().collect();
+                self.stmt("block", &[], &statements)
+            }
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_variable(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Variable(e) => self.node(&e.name.lexeme, Shape::Expression),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_assign(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Assign(e) => self.expr(&format!("{} =", e.name.lexeme), &[&e.value]),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_if(&mut self, e: &StmtExpr) -> usize {
+        match e {
+            StmtExpr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let mut branches: Vec<&StmtExpr> = vec![then_branch];
+                branches.extend(else_branch.as_deref());
+                self.stmt("if", &[condition], &branches)
+            }
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_while(&mut self, e: &StmtExpr) -> usize {
+        match e {
+            StmtExpr::While {
+                condition, body, ..
+            } => self.stmt("while", &[condition], &[body]),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_call(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Call(e) => {
+                let mut children = vec![&e.callee];
+                children.extend(&e.arguments);
+                self.expr("call", &children)
+            }
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_function(&mut self, e: &StmtExpr) -> usize {
+        match e {
+            StmtExpr::Function(function) => self.function(function),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_return(&mut self, e: &StmtExpr) -> usize {
+        match e {
+            StmtExpr::Return { value, .. } => {
+                let value: Vec<&Expr> = value.iter().collect();
+                self.stmt("return", &value, &[])
+            }
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_class(&mut self, e: &StmtExpr) -> usize {
+        match e {
+            StmtExpr::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let label = match superclass {
+                    Some(superclass) => {
+                        format!("class {} < {}", name.lexeme, superclass.name.lexeme)
+                    }
+                    None => format!("class {}", name.lexeme),
+                };
+                let id = self.node(&label, Shape::Statement);
+                for (index, method) in methods.iter().enumerate() {
+                    let child = self.function(method);
+                    self.edge(id, child, index);
+                }
+                id
+            }
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_get(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Get(e) => self.expr(&format!(".{}", e.name.lexeme), &[&e.object]),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_set(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Set(e) => self.expr(&format!(".{} =", e.name.lexeme), &[&e.object, &e.value]),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_this(&mut self, _e: &Expr) -> usize {
+        self.node("this", Shape::Expression)
+    }
+
+    fn visit_super(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Super(e) => self.node(&format!("super.{}", e.method.lexeme), Shape::Expression),
+            _ => self.unknown(),
+        }
+    }
+
+    fn visit_logical(&mut self, e: &Expr) -> usize {
+        match e {
+            Expr::Logical(e) => self.expr(&e.operator.lexeme, &[&e.left, &e.right]),
+            _ => self.unknown(),
+        }
+    }
+}
+
+impl Default for DotPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DotPrinter {
+    pub fn new() -> Self {
+        DotPrinter {
+            nodes: String::new(),
+            edges: String::new(),
+            next_id: 0,
+        }
+    }
+
+    /// The complete graph of every node added so far.
+    pub fn finish(&self) -> String {
+        format!(
+            "digraph ast {{\n    ordering=out;\n    node [fontname=\"monospace\"];\n{}{}}}",
+            self.nodes, self.edges
+        )
+    }
+
+    fn node(&mut self, label: &str, shape: Shape) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        let shape = match shape {
+            Shape::Statement => "box",
+            Shape::Expression => "ellipse",
+        };
+        let _ = writeln!(
+            self.nodes,
+            "    n{} [label=\"{}\", shape={}];",
+            id,
+            escape(label),
+            shape
+        );
+        id
+    }
+
+    fn edge(&mut self, parent: usize, child: usize, index: usize) {
+        let _ = writeln!(
+            self.edges,
+            "    n{} -> n{} [label=\"{}\"];",
+            parent, child, index
+        );
+    }
+
+    /// Adds an expression node followed by its children, in order.
+    fn expr(&mut self, label: &str, children: &[&Expr]) -> usize {
+        let id = self.node(label, Shape::Expression);
+        for (index, child) in children.iter().enumerate() {
+            let child = child.accept(self);
+            self.edge(id, child, index);
+        }
+        id
+    }
+
+    /// Adds a statement node whose children are `exprs` then `stmts`.
+    fn stmt(&mut self, label: &str, exprs: &[&Expr], stmts: &[&StmtExpr]) -> usize {
+        let id = self.node(label, Shape::Statement);
+        let children = exprs
+            .iter()
+            .map(|e| e.accept(self))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .chain(stmts.iter().map(|s| s.accept(self)).collect::<Vec<_>>());
+        for (index, child) in children.enumerate() {
+            self.edge(id, child, index);
+        }
+        id
+    }
+
+    fn function(&mut self, function: &FunctionStmt) -> usize {
+        let params: Vec<&str> = function.params.iter().map(|p| p.lexeme.as_str()).collect();
+        let label = format!("fun {}({})", function.name.lexeme, params.join(", "));
+        let body: Vec<&StmtExpr> = function.body.iter().collect();
+        self.stmt(&label, &[], &body)
+    }
+
+    /// Stands in for a visit called with the wrong kind of node.
+    fn unknown(&mut self) -> usize {
+        self.node("?", Shape::Expression)
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
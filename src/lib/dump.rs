@@ -0,0 +1,78 @@
+//! JSON dumps printed by `rlox tokens --json` and `rlox ast --json`.
+//!
+//! # Schema
+//!
+//! Both commands print a single JSON array. The schema is stable: new fields
+//! may appear, but existing ones keep their names and meaning.
+//!
+//! A **span** is `{"start", "end", "line", "column"}`. `start` and `end` are
+//! byte offsets into the source, `end` exclusive; `line` and `column` are
+//! 1-based and count characters.
+//!
+//! A **token** is `{"type", "lexeme", "literal", "span"}`. `type` names the
+//! kind of token, e.g. `"LeftParen"`, `"Identifier"`, `"Number"` or
+//! `"Eof"`. `literal` is `null` except for numbers, where it is the value in
+//! decimal, and strings, where it is the text with escapes decoded.
+//!
+//! `rlox tokens --json` prints every token, ending with `Eof`.
+//!
+//! `rlox ast --json` prints every top-level **statement**: an object whose
+//! `"stmt"` field names its kind, plus a `span` and these fields:
+//!
+//! | `stmt`       | fields                                                  |
+//! |--------------|---------------------------------------------------------|
+//! | `Expression` | `expression`                                            |
+//! | `Print`      | `expression`                                            |
+//! | `Var`        | `name` (token), `initializer` (expression or null)      |
+//! | `Block`      | `statements`                                            |
+//! | `If`         | `condition`, `then_branch`, `else_branch` (or null)     |
+//! | `While`      | `condition`, `body`                                     |
+//! | `Function`   | `name` (token), `params` (tokens), `body` (statements)  |
+//! | `Return`     | `keyword` (token), `value` (expression or null)         |
+//! | `Class`      | `name` (token), `superclass`, `methods`                 |
+//!
+//! A class's `superclass` is null or `{"name", "span"}`, and each of its
+//! `methods` is a `Function` statement without the `"stmt"` field.
+//!
+//! An **expression** is an object whose `"expr"` field names its kind, plus
+//! a `span` and these fields:
+//!
+//! | `expr`     | fields                                       |
+//! |------------|----------------------------------------------|
+//! | `Binary`   | `left`, `operator` (token), `right`          |
+//! | `Logical`  | `left`, `operator` (token), `right`          |
+//! | `Unary`    | `operator` (token), `right`                  |
+//! | `Grouping` | `expression`                                 |
+//! | `Literal`  | `value`: null, a boolean, number or string   |
+//! | `Variable` | `name` (token)                               |
+//! | `Assign`   | `name` (token), `value`                      |
+//! | `Call`     | `callee`, `paren` (token), `arguments`       |
+//! | `Get`      | `object`, `name` (token)                     |
+//! | `Set`      | `object`, `name` (token), `value`            |
+//! | `This`     | `keyword` (token)                            |
+//! | `Super`    | `keyword` (token), `method` (token)          |
+
+use crate::{parser::StmtExpr, token::Token};
+
+/// Why `--json` is unavailable in builds without the `serde` feature.
+pub const NO_SERDE: &str = "JSON output needs rlox built with the 'serde' feature.";
+
+#[cfg(feature = "serde")]
+pub fn tokens_json(tokens: &[Token]) -> Result<String, String> {
+    serde_json::to_string_pretty(tokens).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+pub fn tokens_json(_tokens: &[Token]) -> Result<String, String> {
+    Err(NO_SERDE.to_string())
+}
+
+#[cfg(feature = "serde")]
+pub fn ast_json(statements: &[StmtExpr]) -> Result<String, String> {
+    serde_json::to_string_pretty(statements).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+pub fn ast_json(_statements: &[StmtExpr]) -> Result<String, String> {
+    Err(NO_SERDE.to_string())
+}
@@ -1,23 +1,110 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{errors::RuntimeError, token::Token, value::Value};
 
 #[derive(Debug, Clone)]
 pub struct Environment {
-    values: HashMap<String, String>,
+    values: HashMap<String, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
+            enclosing: None,
+        }
+    }
+
+    /// Creates a scope nested inside `enclosing`. Lookups that miss here
+    /// fall through to the enclosing scope.
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
         }
     }
 
-    pub fn define(&mut self, name: String, value: String) {
+    pub fn define(&mut self, name: String, value: Value) {
         self.values.insert(name, value);
     }
 
+    /// Names and values defined directly in this scope, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.values.iter()
+    }
+
+    /// Reads a name defined directly in this scope, without walking outward.
+    pub fn get_own(&self, name: &str) -> Option<Value> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = self.values.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(Self::undefined(name)),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
+            *slot = value;
+            return Ok(());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(Self::undefined(name)),
+        }
+    }
+
+    /// Reads `name` from the scope exactly `distance` hops out, as computed
+    /// by the resolver.
+    pub fn get_at(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let scope = Self::ancestor(env, distance).ok_or_else(|| Self::undefined(name))?;
+        let value = scope.borrow().get_own(&name.lexeme);
+        value.ok_or_else(|| Self::undefined(name))
+    }
+
+    pub fn assign_at(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &Token,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let scope = Self::ancestor(env, distance).ok_or_else(|| Self::undefined(name))?;
+        scope.borrow_mut().define(name.lexeme.clone(), value);
+        Ok(())
+    }
 
-    pub fn get(&self, name: &str) -> Option<&String> {
-        self.values.get(name)
+    /// Walks `distance` scopes outward from `env`. Returns `None` if the
+    /// chain is shorter than that, which only happens when the depth did not
+    /// come from resolving this code.
+    pub fn ancestor(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+    ) -> Option<Rc<RefCell<Environment>>> {
+        let mut scope = env.clone();
+        for _ in 0..distance {
+            let enclosing = scope.borrow().enclosing.clone()?;
+            scope = enclosing;
+        }
+        Some(scope)
     }
-}
\ No newline at end of file
+
+    fn undefined(name: &Token) -> RuntimeError {
+        RuntimeError::new(name, &format!("Undefined variable '{}'.", name.lexeme))
+    }
+}
@@ -1,15 +1,60 @@
-use std::{num::ParseIntError, str::ParseBoolError};
-
 use thiserror::Error;
 
+use crate::{
+    token::{Span, Token},
+    value::Value,
+};
+
 #[derive(Debug, Error)]
-pub enum Runtime {
-    #[error("Not an integer")]
-    NotInt(#[from] ParseIntError),
-    #[error("Not a string")]
-    NotString,
-    #[error("Operation is invalid")]
-    InvalidOperation,
-    #[error("Not a boolean")]
-    NotBool(#[from] ParseBoolError),
-}
\ No newline at end of file
+pub enum RuntimeError {
+    #[error("{message}\n[line {}]", token.span.line)]
+    Error { token: Token, message: String },
+    /// Not a real error: a `return` statement unwinds through the
+    /// interpreter as this variant until the enclosing call catches it.
+    #[error("Can't return from top-level code.")]
+    Return(Value),
+}
+
+impl RuntimeError {
+    pub fn new(token: &Token, message: &str) -> Self {
+        RuntimeError::Error {
+            token: token.clone(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A compile-time error at `token`, reported by the parser or the resolver.
+#[derive(Debug, Error, Clone)]
+#[error("{message}")]
+pub struct ParseError {
+    pub token: Token,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(token: &Token, message: &str) -> Self {
+        Self {
+            token: token.clone(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A runtime error raised by the bytecode VM, at the span of the
+/// instruction that failed.
+#[derive(Debug, Error, Clone)]
+#[error("{message}\n[line {}]", span.line)]
+pub struct VmError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl VmError {
+    pub fn new(span: Span, message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+            span,
+        }
+    }
+}
@@ -1,175 +1,532 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+};
+
 use crate::{
-    parser::{BinaryExpr, Expr, ExprVisitor, LiteralExpr, StmtExpr, UnaryExpr, Visitor, VariableExpr},
-    token_type::TokenType, env::Environment,
+    callable::{LoxCallable, LoxFunction, MAX_CALL_DEPTH},
+    class::{LoxClass, LoxInstance},
+    env::Environment,
+    errors::RuntimeError,
+    natives::{self, NativeFunction},
+    parser::{BinaryExpr, Expr, ExprVisitor, LiteralExpr, StmtExpr, UnaryExpr, Visitor},
+    token::Token,
+    token_type::TokenType,
+    value::Value,
 };
-#[derive(Clone, Debug)]
+
+pub type RuntimeResult = Result<Value, RuntimeError>;
+
 pub struct Interpreter {
-    environment: Environment,
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    /// Scope depth of each resolved local, keyed by expression id.
+    /// Variables missing from here are globals.
+    locals: HashMap<usize, usize>,
+    /// Where `print` statements write. Standard output by default.
+    output: Box<dyn Write>,
+    /// Lox calls currently in progress, up to [`MAX_CALL_DEPTH`].
+    depth: usize,
+}
+
+impl fmt::Debug for Interpreter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interpreter")
+            .field("globals", &self.globals)
+            .field("environment", &self.environment)
+            .field("locals", &self.locals)
+            .finish_non_exhaustive()
+    }
 }
-impl ExprVisitor<Option<String>> for Interpreter {
-    fn visit_binary(&mut self, e: &Expr) -> Option<String> {
+
+/// Adapts a closure to [`Write`]. Each `print` statement reaches it as one
+/// call with the whole line, newline included.
+pub(crate) struct FnOutput<F>(pub(crate) F);
+
+impl<F: FnMut(&str)> Write for FnOutput<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl ExprVisitor<RuntimeResult> for Interpreter {
+    fn visit_binary(&mut self, e: &Expr) -> RuntimeResult {
         if let Expr::Binary(a) = e {
-            let binary_expr = &**a;
-            if let BinaryExpr {
+            let BinaryExpr {
                 left,
                 operator,
                 right,
-            } = binary_expr
-            {
-                // let parsed_left: isize = self.evaluate(left).unwrap().parse().unwrap();
-                // let parsed_right: isize = self.evaluate(right).unwrap().parse().unwrap();
-                match operator.tty {
-                    TokenType::Greater => {
-                        let parsed_left: isize = self.evaluate(left).unwrap().parse().unwrap();
-                        let parsed_right: isize = self.evaluate(right).unwrap().parse().unwrap();
-                        return Some(format!("{}", parsed_left > parsed_right));
-                    }
-                    TokenType::GreaterEqual => {
-                        let parsed_left: isize = self.evaluate(left).unwrap().parse().unwrap();
-                        let parsed_right: isize = self.evaluate(right).unwrap().parse().unwrap();
-                        return Some(format!("{}", parsed_left >= parsed_right));
-                    }
-                    TokenType::Less => {
-                        let parsed_left: isize = self.evaluate(left).unwrap().parse().unwrap();
-                        let parsed_right: isize = self.evaluate(right).unwrap().parse().unwrap();
-                        return Some(format!("{}", parsed_left < parsed_right));
-                    }
-                    TokenType::LessEqual => {
-                        let parsed_left: isize = self.evaluate(left).unwrap().parse().unwrap();
-                        let parsed_right: isize = self.evaluate(right).unwrap().parse().unwrap();
-                        return Some(format!("{}", parsed_left <= parsed_right));
-                    }
-                    TokenType::BangEqual => {
-                        let a = self.evaluate(left);
-                        let b = self.evaluate(right);
-                        let res = !self.is_equal(&a, &b).unwrap().parse::<bool>().unwrap();
-                        return Some(format!("{}", res));
-                    }
-                    TokenType::EqualEqual => {
-                        let a = self.evaluate(left);
-                        let b = self.evaluate(right);
-                        return self.is_equal(&a, &b);
+                ..
+            } = &**a;
+            let left = self.evaluate(left)?;
+            let right = self.evaluate(right)?;
+            return match operator.tty {
+                TokenType::Greater => {
+                    let (l, r) = self.number_operands(operator, &left, &right)?;
+                    Ok(Value::Bool(l > r))
+                }
+                TokenType::GreaterEqual => {
+                    let (l, r) = self.number_operands(operator, &left, &right)?;
+                    Ok(Value::Bool(l >= r))
+                }
+                TokenType::Less => {
+                    let (l, r) = self.number_operands(operator, &left, &right)?;
+                    Ok(Value::Bool(l < r))
+                }
+                TokenType::LessEqual => {
+                    let (l, r) = self.number_operands(operator, &left, &right)?;
+                    Ok(Value::Bool(l <= r))
+                }
+                TokenType::BangEqual => Ok(Value::Bool(left != right)),
+                TokenType::EqualEqual => Ok(Value::Bool(left == right)),
+                TokenType::Minus => {
+                    let (l, r) = self.number_operands(operator, &left, &right)?;
+                    Ok(Value::Number(l - r))
+                }
+                TokenType::Star => {
+                    let (l, r) = self.number_operands(operator, &left, &right)?;
+                    Ok(Value::Number(l * r))
+                }
+                TokenType::Slash => {
+                    // Dividing by zero gives an infinity or NaN, as in IEEE 754.
+                    let (l, r) = self.number_operands(operator, &left, &right)?;
+                    Ok(Value::Number(l / r))
+                }
+                TokenType::Plus => match (left, right) {
+                    (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
+                    (Value::String(mut l), Value::String(r)) => {
+                        l.push_str(&r);
+                        Ok(Value::String(l))
                     }
+                    _ => Err(RuntimeError::new(
+                        operator,
+                        "Operands must be two numbers or two strings.",
+                    )),
+                },
+                _ => Err(RuntimeError::new(operator, "Unknown binary operator.")),
+            };
+        }
+        Ok(Value::Nil)
+    }
+    fn visit_literal(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Literal(LiteralExpr { value, .. }) = e {
+            return Ok(value.clone());
+        }
+        Ok(Value::Nil)
+    }
+    fn visit_unary(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Unary(a) = e {
+            let UnaryExpr { operator, right, .. } = &**a;
+            let right = self.evaluate(right)?;
+            return match operator.tty {
+                TokenType::Minus => match right {
+                    Value::Number(n) => Ok(Value::Number(-n)),
+                    _ => Err(RuntimeError::new(operator, "Operand must be a number.")),
+                },
+                TokenType::Bang => Ok(Value::Bool(!right.is_truthy())),
+                _ => Err(RuntimeError::new(operator, "Unknown unary operator.")),
+            };
+        }
+        Ok(Value::Nil)
+    }
+    fn visit_grouping(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Grouping(g) = e {
+            return self.evaluate(&g.expression);
+        }
+        Ok(Value::Nil)
+    }
 
-                    TokenType::Minus => {
-                        let parsed_left: isize = self.evaluate(left).unwrap().parse().unwrap();
-                        let parsed_right: isize = self.evaluate(right).unwrap().parse().unwrap();
-                        return Some(format!("{}", parsed_left - parsed_right));
-                    }
-                    TokenType::Star => {
-                        let parsed_left: isize = self.evaluate(left).unwrap().parse().unwrap();
-                        let parsed_right: isize = self.evaluate(right).unwrap().parse().unwrap();
-                        return Some(format!("{}", parsed_left * parsed_right));
-                    }
-                    TokenType::Slash => {
-                        let parsed_left: isize = self.evaluate(left).unwrap().parse().unwrap();
-                        let parsed_right: isize = self.evaluate(right).unwrap().parse().unwrap();
-                        return Some(format!("{}", parsed_left / parsed_right));
-                    }
-                    TokenType::Plus => {
-                        let parsed_left = self.evaluate(left).unwrap().parse::<isize>();
-                        let parsed_right = self.evaluate(right).unwrap().parse::<isize>();
-                        if parsed_left.is_err() && parsed_right.is_err() {
-                            let mut evaluated_left = self.evaluate(left).unwrap();
-                            let evaluated_right = self.evaluate(right).unwrap();
-                            evaluated_left.push_str(&evaluated_right);
-                        }
-                        return Some(format!("{}", parsed_left.unwrap() + parsed_right.unwrap()));
-                    }
-                    _ => (),
-                }
+    fn visit_print(&mut self, e: &StmtExpr) -> RuntimeResult {
+        let value = self.evaluate(&e.get_inner())?;
+        // Like `println!`, but a closed output is not worth aborting the
+        // program over.
+        let _ = self.output.write_all(format!("{}\n", value).as_bytes());
+        Ok(Value::Nil)
+    }
+
+    fn visit_expression(&mut self, e: &StmtExpr) -> RuntimeResult {
+        self.evaluate(&e.get_inner())?;
+        Ok(Value::Nil)
+    }
+
+    fn visit_var(&mut self, e: &StmtExpr) -> RuntimeResult {
+        if let StmtExpr::Var { name, .. } = e {
+            let value = self.evaluate(&e.get_inner())?;
+            self.environment
+                .borrow_mut()
+                .define(name.lexeme.clone(), value);
+        }
+        Ok(Value::Nil)
+    }
+
+    fn visit_block(&mut self, e: &StmtExpr) -> RuntimeResult {
+        if let StmtExpr::Block { statements, .. } = e {
+            let environment = Environment::with_enclosing(self.environment.clone());
+            self.execute_block(statements, environment)?;
+        }
+        Ok(Value::Nil)
+    }
+
+    fn visit_if(&mut self, e: &StmtExpr) -> RuntimeResult {
+        if let StmtExpr::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } = e
+        {
+            if self.evaluate(condition)?.is_truthy() {
+                self.execute(then_branch)?;
+            } else if let Some(else_branch) = else_branch {
+                self.execute(else_branch)?;
             }
         }
-        None
+        Ok(Value::Nil)
     }
-    fn visit_literal(&mut self, e: &Expr) -> Option<String> {
-        if let Expr::Literal(LiteralExpr { value }) = e {
-            return value.clone();
+
+    fn visit_while(&mut self, e: &StmtExpr) -> RuntimeResult {
+        if let StmtExpr::While { condition, body, .. } = e {
+            while self.evaluate(condition)?.is_truthy() {
+                self.execute(body)?;
+            }
         }
-        None
+        Ok(Value::Nil)
+    }
+
+    fn visit_logical(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Logical(l) = e {
+            let left = self.evaluate(&l.left)?;
+            // Short-circuit: the deciding operand is the result, not a bool.
+            if l.operator.tty == TokenType::Or {
+                if left.is_truthy() {
+                    return Ok(left);
+                }
+            } else if !left.is_truthy() {
+                return Ok(left);
+            }
+            return self.evaluate(&l.right);
+        }
+        Ok(Value::Nil)
     }
-    fn visit_unary(&mut self, e: &Expr) -> Option<String> {
-        if let Expr::Unary(a) = e {
-            let unary_expr = &**a;
-            if let UnaryExpr { operator, right } = unary_expr {
-                let rig = self.evaluate(&right.clone());
-                match operator.tty {
-                    TokenType::Minus => {
-                        return Some(format!("-{}", rig.unwrap()));
-                    }
-                    TokenType::Bang => return self.is_truthy(&rig),
 
-                    _ => (),
+    fn visit_call(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Call(c) = e {
+            let callee = self.evaluate(&c.callee)?;
+            let mut arguments = Vec::with_capacity(c.arguments.len());
+            for argument in &c.arguments {
+                arguments.push(self.evaluate(argument)?);
+            }
+            let function: Rc<dyn LoxCallable> = match callee {
+                Value::Callable(function) => function,
+                Value::Native(native) => native,
+                Value::Class(class) => class,
+                _ => {
+                    return Err(RuntimeError::new(
+                        &c.paren,
+                        "Can only call functions and classes.",
+                    ))
                 }
+            };
+            if arguments.len() != function.arity() {
+                return Err(RuntimeError::new(
+                    &c.paren,
+                    &format!(
+                        "Expected {} arguments but got {}.",
+                        function.arity(),
+                        arguments.len()
+                    ),
+                ));
+            }
+            if self.depth == MAX_CALL_DEPTH {
+                return Err(RuntimeError::new(&c.paren, "Stack overflow."));
             }
+            self.depth += 1;
+            let result = function.call(self, &c.paren, arguments);
+            self.depth -= 1;
+            return result;
+        }
+        Ok(Value::Nil)
+    }
+
+    fn visit_function(&mut self, e: &StmtExpr) -> RuntimeResult {
+        if let StmtExpr::Function(declaration) = e {
+            let function =
+                LoxFunction::new(declaration.clone(), self.environment.clone(), false);
+            self.environment.borrow_mut().define(
+                declaration.name.lexeme.clone(),
+                Value::Callable(Rc::new(function)),
+            );
         }
-        None
+        Ok(Value::Nil)
     }
-    fn visit_grouping(&mut self, e: &Expr) -> Option<String> {
-        self.evaluate(e)
+
+    fn visit_return(&mut self, e: &StmtExpr) -> RuntimeResult {
+        let value = self.evaluate(&e.get_inner())?;
+        Err(RuntimeError::Return(value))
     }
 
-    fn visit_print(&mut self, e: &crate::parser::StmtExpr) -> Option<String> {
-        let value = self.evaluate(&e.get_inner());
-        match value {
-            Some(v) => {
+    fn visit_class(&mut self, e: &StmtExpr) -> RuntimeResult {
+        if let StmtExpr::Class {
+            name,
+            superclass,
+            methods,
+            ..
+        } = e
+        {
+            let superclass = match superclass {
+                Some(variable) => match self.evaluate(variable)? {
+                    Value::Class(class) => Some(class),
+                    _ => {
+                        return Err(RuntimeError::new(
+                            &variable.name,
+                            "Superclass must be a class.",
+                        ))
+                    }
+                },
+                None => None,
+            };
 
-                println!("{}", v)
+            self.environment
+                .borrow_mut()
+                .define(name.lexeme.clone(), Value::Nil);
+
+            // Methods of a subclass close over an extra scope holding `super`.
+            let mut method_env = self.environment.clone();
+            if let Some(superclass) = &superclass {
+                let mut environment = Environment::with_enclosing(method_env);
+                environment.define("super".to_string(), Value::Class(superclass.clone()));
+                method_env = Rc::new(RefCell::new(environment));
             }
-            None => (),
+
+            let methods = methods
+                .iter()
+                .map(|method| {
+                    let is_initializer = method.name.lexeme == "init";
+                    let function =
+                        LoxFunction::new(method.clone(), method_env.clone(), is_initializer);
+                    (method.name.lexeme.clone(), Rc::new(function))
+                })
+                .collect();
+            let class = LoxClass::new(name.lexeme.clone(), superclass, methods);
+            self.environment
+                .borrow_mut()
+                .assign(name, Value::Class(Rc::new(class)))?;
         }
-        None
+        Ok(Value::Nil)
     }
 
-    fn visit_expression(&mut self, e: &crate::parser::StmtExpr) -> Option<String> {
-        self.evaluate(&e.get_inner());
-        None
+    fn visit_get(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Get(g) = e {
+            return match self.evaluate(&g.object)? {
+                Value::Instance(instance) => LoxInstance::get(&instance, &g.name),
+                _ => Err(RuntimeError::new(
+                    &g.name,
+                    "Only instances have properties.",
+                )),
+            };
+        }
+        Ok(Value::Nil)
     }
 
-    fn visit_var(&mut self, e: &VariableExpr) -> Option<String> {
-        let name = e.get_name();
-        let value = self.evaluate(e);
-        self.environment.define(name, value.unwrap_or_else(|| "null".to_string()));
-        None
+    fn visit_set(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Set(s) = e {
+            let instance = match self.evaluate(&s.object)? {
+                Value::Instance(instance) => instance,
+                _ => return Err(RuntimeError::new(&s.name, "Only instances have fields.")),
+            };
+            let value = self.evaluate(&s.value)?;
+            instance.borrow_mut().set(&s.name, value.clone());
+            return Ok(value);
+        }
+        Ok(Value::Nil)
+    }
+
+    fn visit_this(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::This(t) = e {
+            return self.look_up_variable(&t.keyword, t.id);
+        }
+        Ok(Value::Nil)
+    }
+
+    fn visit_super(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Super(s) = e {
+            // Only code that skipped the resolver can get here without a
+            // depth, and then `super` is as undefined as any other local.
+            let undefined = || RuntimeError::new(&s.keyword, "Undefined variable 'super'.");
+            let distance = *self.locals.get(&s.id).ok_or_else(undefined)?;
+            let superclass = Environment::get_at(&self.environment, distance, &s.keyword)?;
+            // `this` lives in the scope just inside the one binding `super`.
+            let object = distance
+                .checked_sub(1)
+                .and_then(|distance| Environment::ancestor(&self.environment, distance))
+                .and_then(|scope| scope.borrow().get_own("this"))
+                .ok_or_else(undefined)?;
+            let method = match &superclass {
+                Value::Class(class) => class.find_method(&s.method.lexeme),
+                _ => None,
+            };
+            return match method {
+                Some(method) => Ok(Value::Callable(Rc::new(method.bind(object)))),
+                None => Err(RuntimeError::new(
+                    &s.method,
+                    &format!("Undefined property '{}'.", s.method.lexeme),
+                )),
+            };
+        }
+        Ok(Value::Nil)
+    }
+
+    fn visit_variable(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Variable(v) = e {
+            return self.look_up_variable(&v.name, v.id);
+        }
+        Ok(Value::Nil)
+    }
+
+    fn visit_assign(&mut self, e: &Expr) -> RuntimeResult {
+        if let Expr::Assign(a) = e {
+            let value = self.evaluate(&a.value)?;
+            match self.locals.get(&a.id) {
+                Some(distance) => {
+                    Environment::assign_at(&self.environment, *distance, &a.name, value.clone())?
+                }
+                None => self.globals.borrow_mut().assign(&a.name, value.clone())?,
+            }
+            return Ok(value);
+        }
+        Ok(Value::Nil)
     }
+}
 
-    
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let env = Environment::new();
-        Self { environment: env }
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let mut interpreter = Self {
+            environment: globals.clone(),
+            globals,
+            locals: HashMap::new(),
+            output: Box::new(io::stdout()),
+            depth: 0,
+        };
+        interpreter.define_builtins();
+        interpreter
     }
-    pub fn evaluate(&mut self, expr: &impl Visitor<Option<String>>) -> Option<String> {
-        expr.accept(self)
+
+    /// Sends the output of `print` statements to `output`.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
     }
-    pub fn is_truthy(&mut self, expr: &Option<String>) -> Option<String> {
-        if expr.clone().unwrap() == "null" {
-            return Some("false".to_string());
-        }
-        let to_bool: bool = expr.clone().unwrap().parse().unwrap();
-        return Some(format!("{}", !to_bool));
+
+    /// Calls `handler` with each line printed by a `print` statement.
+    pub fn set_output_fn(&mut self, handler: impl FnMut(&str) + 'static) {
+        self.set_output(FnOutput(handler));
+    }
+
+    /// Forgets every definition, keeping the output settings.
+    pub fn reset(&mut self) {
+        self.globals = Rc::new(RefCell::new(Environment::new()));
+        self.environment = self.globals.clone();
+        self.locals.clear();
+        self.define_builtins();
     }
-    pub fn is_equal(&mut self, a: &Option<String>, b: &Option<String>) -> Option<String> {
-        if a.clone().unwrap() == "null" && b.clone().unwrap() == "null" {
-            return Some("true".to_string());
+
+    fn define_builtins(&mut self) {
+        for native in natives::builtins() {
+            self.define_global(native.name().to_string(), Value::Native(native));
         }
-        if a.clone().unwrap() == "null" {
-            return Some("false".to_string());
+    }
+
+    pub fn globals(&self) -> Rc<RefCell<Environment>> {
+        self.globals.clone()
+    }
+
+    /// Exposes a Rust closure to Lox code as a global function called
+    /// `name`. Calls with a different number of arguments than `arity` are
+    /// rejected before the closure runs.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        let native = NativeFunction::new(name, arity, function);
+        self.define_global(name.to_string(), Value::Native(Rc::new(native)));
+    }
+
+    /// Defines or overwrites the global `name`.
+    pub fn define_global(&mut self, name: String, value: Value) {
+        self.globals.borrow_mut().define(name, value);
+    }
+
+    pub fn evaluate(&mut self, expr: &impl Visitor<RuntimeResult>) -> RuntimeResult {
+        expr.accept(self)
+    }
+
+    /// Records that the variable expression `id` refers to a local declared
+    /// `depth` scopes out from where it is used.
+    pub fn resolve(&mut self, id: usize, depth: usize) {
+        self.locals.insert(id, depth);
+    }
+
+    fn look_up_variable(&self, name: &Token, id: usize) -> RuntimeResult {
+        match self.locals.get(&id) {
+            Some(distance) => Environment::get_at(&self.environment, *distance, name),
+            None => self.globals.borrow().get(name),
         }
-        Some(format!("{}", a.clone().unwrap() == b.clone().unwrap()))
     }
 
-    pub fn interpret(&mut self, statements: Vec<StmtExpr>) {
-        for stmt_expr in statements {
-            self.execute(stmt_expr);
+    fn number_operands(
+        &self,
+        operator: &Token,
+        left: &Value,
+        right: &Value,
+    ) -> Result<(f64, f64), RuntimeError> {
+        match (left, right) {
+            (Value::Number(l), Value::Number(r)) => Ok((*l, *r)),
+            _ => Err(RuntimeError::new(operator, "Operands must be numbers.")),
         }
     }
 
-    pub fn execute(&mut self, stmt_expr: StmtExpr) {
-        stmt_expr.accept(self);
+    pub fn interpret(&mut self, statements: Vec<StmtExpr>) -> Result<(), RuntimeError> {
+        for stmt_expr in &statements {
+            self.execute(stmt_expr)?;
+        }
+        Ok(())
     }
 
+    pub fn execute(&mut self, stmt_expr: &StmtExpr) -> Result<(), RuntimeError> {
+        stmt_expr.accept(self)?;
+        Ok(())
+    }
 
+    /// Runs `statements` inside `environment`, restoring the current scope
+    /// afterwards even if one of them fails.
+    pub fn execute_block(
+        &mut self,
+        statements: &[StmtExpr],
+        environment: Environment,
+    ) -> Result<(), RuntimeError> {
+        let previous = std::mem::replace(
+            &mut self.environment,
+            Rc::new(RefCell::new(environment)),
+        );
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement));
+        self.environment = previous;
+        result
+    }
 }
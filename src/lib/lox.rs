@@ -1,62 +1,481 @@
+use std::cell::RefCell;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, Read, Write};
+use std::rc::Rc;
 
+use crate::ast_printer::{self, Notation};
+use crate::compiler::Compiler;
+use crate::diagnostics::{self, Diagnostic, DiagnosticHandler, Format};
+use crate::dot_printer;
+use crate::dump;
+use crate::errors::{ParseError, RuntimeError};
+use crate::interpreter::{FnOutput, Interpreter};
+use crate::natives::NativeFunction;
+use crate::parser::{Parser, StmtExpr};
+use crate::repl::Repl;
+use crate::resolver::Resolver;
 use crate::scanner::Scanner;
-pub struct Lox;
+use crate::token::{Span, Token};
+use crate::value::Value;
+use crate::vm::Vm;
 
-// hadError not implmented.
+// Exit codes, following BSD sysexits.h.
+pub const EX_OK: i32 = 0;
+pub const EX_USAGE: i32 = 64;
+pub const EX_DATAERR: i32 = 65;
+pub const EX_NOINPUT: i32 = 66;
+pub const EX_SOFTWARE: i32 = 70;
+
+const USAGE: &str = "\
+Usage: rlox [--vm] [--error-format=human|json] [--print-ast[=rpn]] [script]
+       rlox tokens [--json] <script>
+       rlox ast [--json|--dot] <script>";
+
+/// How `rlox tokens` and `rlox ast` print what they read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Text,
+    Json,
+    /// A Graphviz graph; only `rlox ast` supports it.
+    Dot,
+}
+
+/// Which engine runs programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Walks the syntax tree directly.
+    #[default]
+    Interpreter,
+    /// Compiles to bytecode and runs it on a stack machine.
+    Vm,
+}
+
+pub struct Lox {
+    interpreter: Interpreter,
+    vm: Vm,
+    backend: Backend,
+    /// Every text scanned so far, which diagnostics are rendered against.
+    sources: Vec<Source>,
+    /// The name the next text scanned is referred to by.
+    source_name: String,
+    format: Format,
+    /// When set, programs are printed in this notation instead of run.
+    print_ast: Option<Notation>,
+    /// Receives diagnostics instead of stderr when set.
+    diagnostic_handler: Option<Box<DiagnosticHandler>>,
+    /// Set by the reporting functions below so the driver knows whether to
+    /// keep going.
+    had_error: bool,
+    had_runtime_error: bool,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Lox {
-    pub fn run(f: Vec<&str>) {
-        if f.len() > 1 {
-            eprintln!("Usage rlox: [script]");
-        } else if f.len() == 1 {
-            Lox::run_file(f[0]);
-        } else {
-            Lox::run_prompt();
+    pub fn new() -> Self {
+        Self {
+            interpreter: Interpreter::new(),
+            vm: Vm::new(),
+            backend: Backend::default(),
+            sources: Vec::new(),
+            source_name: "<input>".to_string(),
+            format: Format::default(),
+            print_ast: None,
+            diagnostic_handler: None,
+            had_error: false,
+            had_runtime_error: false,
+        }
+    }
+
+    /// Entry point for the `rlox` command line. Returns the process exit
+    /// code.
+    pub fn run_args(&mut self, args: &[String]) -> i32 {
+        let (command, args) = match args.first().map(String::as_str) {
+            Some(command @ ("tokens" | "ast")) => (Some(command), &args[1..]),
+            _ => (None, args),
+        };
+        let mut dump_format = DumpFormat::Text;
+        let mut paths = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "--json" if command.is_some() => {
+                    if !cfg!(feature = "serde") {
+                        eprintln!("{}\n{}", dump::NO_SERDE, USAGE);
+                        return EX_USAGE;
+                    }
+                    dump_format = DumpFormat::Json
+                }
+                "--dot" if command == Some("ast") => dump_format = DumpFormat::Dot,
+                "--vm" => self.backend = Backend::Vm,
+                "--error-format=human" => self.format = Format::Human,
+                "--error-format=json" => self.format = Format::Json,
+                "--print-ast" => self.print_ast = Some(Notation::SExpression),
+                "--print-ast=rpn" => self.print_ast = Some(Notation::Rpn),
+                _ if arg.starts_with("--") => {
+                    eprintln!("{}", USAGE);
+                    return EX_USAGE;
+                }
+                _ => paths.push(arg),
+            }
+        }
+        match (command, paths.as_slice()) {
+            (None, []) => {
+                self.run_prompt();
+                EX_OK
+            }
+            (None, [path]) => self.run_file(path),
+            (Some("tokens"), [path]) => {
+                self.dump_tokens(path, dump_format == DumpFormat::Json)
+            }
+            (Some(_), [path]) => self.dump_ast(path, dump_format),
+            _ => {
+                eprintln!("{}", USAGE);
+                EX_USAGE
+            }
+        }
+    }
+
+    pub fn run_file(&mut self, path: &str) -> i32 {
+        self.reset_errors();
+        let buffer = match self.read_source(path) {
+            Ok(buffer) => buffer,
+            Err(code) => return code,
+        };
+        self.run(&buffer);
+        if self.had_error {
+            return EX_DATAERR;
+        }
+        if self.had_runtime_error {
+            return EX_SOFTWARE;
+        }
+        EX_OK
+    }
+
+    /// `rlox tokens`: prints the tokens scanned from the file at `path`.
+    pub fn dump_tokens(&mut self, path: &str, json: bool) -> i32 {
+        let source = match self.read_source(path) {
+            Ok(source) => source,
+            Err(code) => return code,
+        };
+        let tokens = self.scan(&source);
+        if self.had_error {
+            return EX_DATAERR;
         }
+        if json {
+            return print_json(dump::tokens_json(&tokens));
+        }
+        for token in &tokens {
+            println!("{}", token);
+        }
+        EX_OK
+    }
+
+    /// `rlox ast`: prints the syntax tree parsed from the file at `path`.
+    pub fn dump_ast(&mut self, path: &str, format: DumpFormat) -> i32 {
+        let source = match self.read_source(path) {
+            Ok(source) => source,
+            Err(code) => return code,
+        };
+        let tokens = self.scan(&source);
+        let (statements, errors) = Parser::new(tokens).parse();
+        for error in &errors {
+            self.parse_error(error);
+        }
+        if self.had_error {
+            return EX_DATAERR;
+        }
+        match format {
+            DumpFormat::Text => {
+                println!("{}", ast_printer::print_program(&statements, Notation::SExpression))
+            }
+            DumpFormat::Json => return print_json(dump::ast_json(&statements)),
+            DumpFormat::Dot => println!("{}", dot_printer::print_program(&statements)),
+        }
+        EX_OK
     }
 
-    fn run_file(path: &str) {
+    /// Reads the file at `path`, which later diagnostics will refer to. On
+    /// failure, reports it and returns the exit code.
+    fn read_source(&mut self, path: &str) -> Result<String, i32> {
         let mut buffer = String::new();
-        let file = File::open(path).expect("File not found");
-        let mut reader = BufReader::new(file);
-        reader.read_to_string(&mut buffer).expect("Read error");
-        Lox::run_line(&buffer);
-    }
-
-    fn run_prompt() {
-        loop {
-            print!("> ");
-            let mut buffer = String::new();
-            let input = std::io::stdin()
-                .read_line(&mut buffer);
-            match input {
-                Err(_) => {
-                    break;
-                },
-                Ok(_) => {
-                    Lox::run_line(&buffer);
+        let read = File::open(path)
+            .and_then(|file| BufReader::new(file).read_to_string(&mut buffer));
+        if let Err(error) = read {
+            eprintln!("Could not read '{}': {}", path, error);
+            return Err(EX_NOINPUT);
+        }
+        self.source_name = path.to_string();
+        Ok(buffer)
+    }
+
+    pub fn run_prompt(&mut self) {
+        self.source_name = "<repl>".to_string();
+        Repl::new(self).run();
+    }
+
+    /// Scans, parses, resolves and interprets `source`. Errors are reported
+    /// to stderr as they are found and recorded in the error flags, which
+    /// start out clear.
+    pub fn run(&mut self, source: &str) {
+        self.reset_errors();
+        let tokens = self.scan(source);
+        if self.had_error {
+            return;
+        }
+        self.run_tokens(tokens);
+    }
+
+    /// Scans `source`, reporting any errors. The text is kept, so that
+    /// diagnostics about its tokens can show it even after later scans.
+    pub fn scan(&mut self, source: &str) -> Vec<Token> {
+        // Each text's spans start where the previous text's ended, so a span
+        // alone tells which text it is in. The gap keeps one text's
+        // end-of-file span apart from the next text's first token.
+        let offset = self
+            .sources
+            .last()
+            .map_or(0, |source| source.offset + source.text.len() + 1);
+        self.sources.push(Source {
+            name: self.source_name.clone(),
+            text: source.to_string(),
+            offset,
+        });
+        let mut scanner = Scanner::new(source);
+        let mut tokens = scanner.scan_tokens();
+        for token in &mut tokens {
+            token.span = shift(token.span, offset as isize);
+        }
+        for error in scanner.errors() {
+            let mut error = error.clone();
+            error.primary.span = shift(error.primary.span, offset as isize);
+            self.error(&error);
+        }
+        tokens
+    }
+
+    pub fn run_tokens(&mut self, tokens: Vec<Token>) {
+        let mut parser = Parser::new(tokens);
+        let (statements, errors) = parser.parse();
+        for error in &errors {
+            self.parse_error(error);
+        }
+        if self.had_error {
+            return;
+        }
+        if let Some(notation) = self.print_ast {
+            println!("{}", ast_printer::print_program(&statements, notation));
+            return;
+        }
+        self.execute(statements);
+    }
+
+    /// Resolves already parsed statements and runs them on the current
+    /// backend.
+    pub fn execute(&mut self, statements: Vec<StmtExpr>) {
+        let errors = Resolver::new(&mut self.interpreter).resolve(&statements);
+        for error in &errors {
+            self.error(error);
+        }
+        if self.had_error {
+            return;
+        }
+
+        match self.backend {
+            Backend::Interpreter => {
+                if let Err(error) = self.interpreter.interpret(statements) {
+                    self.runtime_error(&error);
+                }
+            }
+            Backend::Vm => {
+                let script = Compiler::new().compile(&statements);
+                if let Err(error) = self.vm.interpret(script) {
+                    self.report(&Diagnostic::from(&error));
+                    self.had_runtime_error = true;
                 }
             }
-            
         }
     }
 
-    fn run_line(src: &str) {
-        let mut scanner = Scanner::new(src);
-        let tokens = scanner.scan_tokens();
-        for token in tokens {
-            println!("{:?}", token.to_string())
+    /// Discards every definition, keeping the output settings.
+    pub fn reset(&mut self) {
+        self.interpreter.reset();
+        self.vm.reset();
+    }
+
+    /// Sends the output of `print` statements to `output`, whichever
+    /// backend runs them.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        let output = SharedOutput(Rc::new(RefCell::new(Box::new(output))));
+        self.interpreter.set_output(output.clone());
+        self.vm.set_output(output);
+    }
+
+    /// Calls `handler` with each line printed, on either backend.
+    pub fn set_output_fn(&mut self, handler: impl FnMut(&str) + 'static) {
+        self.set_output(FnOutput(handler));
+    }
+
+    /// Like [`Interpreter::define_native`], but the native is visible to
+    /// both backends.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        let native = Rc::new(NativeFunction::new(name, arity, function));
+        self.interpreter
+            .define_global(name.to_string(), Value::Native(native.clone()));
+        self.vm.define_global(name.to_string(), Value::Native(native));
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    /// Gives access to the tree-walking backend alone.
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// Gives access to the bytecode backend alone.
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    /// Passes every diagnostic to `handler` rather than printing it to
+    /// stderr. The error flags are still set.
+    pub fn set_diagnostic_handler(&mut self, handler: impl FnMut(&Diagnostic) + 'static) {
+        self.diagnostic_handler = Some(Box::new(handler));
+    }
+
+    /// Clears the error flags, e.g. before the next REPL entry.
+    pub fn reset_errors(&mut self) {
+        self.had_error = false;
+        self.had_runtime_error = false;
+    }
+
+    pub fn had_error(&self) -> bool {
+        self.had_error
+    }
+
+    pub fn had_runtime_error(&self) -> bool {
+        self.had_runtime_error
+    }
+
+    pub fn error(&mut self, diagnostic: &Diagnostic) {
+        self.report(diagnostic);
+        self.had_error = true;
+    }
+
+    pub fn parse_error(&mut self, error: &ParseError) {
+        self.error(&Diagnostic::from(error));
+    }
+
+    pub fn runtime_error(&mut self, error: &RuntimeError) {
+        self.report(&Diagnostic::from(error));
+        self.had_runtime_error = true;
+    }
+
+    /// Reports `diagnostic` against the text its primary span is in, with
+    /// spans counted from the start of that text.
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        let start = diagnostic.primary.span.start;
+        let (name, text, diagnostic) =
+            match self.sources.iter().rev().find(|source| source.offset <= start) {
+                Some(source) => (
+                    source.name.as_str(),
+                    source.text.as_str(),
+                    source.relative(diagnostic),
+                ),
+                None => (self.source_name.as_str(), "", diagnostic.clone()),
+            };
+        if let Some(handler) = &mut self.diagnostic_handler {
+            handler(&diagnostic);
+            return;
+        }
+        match self.format {
+            Format::Human => eprint!(
+                "{}",
+                diagnostic.render(text, name, diagnostics::stderr_supports_color())
+            ),
+            Format::Json => eprintln!("{}", diagnostic.to_json(name)),
+        }
+    }
+}
+
+/// A text that was scanned, and where its spans start.
+struct Source {
+    name: String,
+    text: String,
+    offset: usize,
+}
+
+impl Source {
+    /// `diagnostic` with its spans counted from the start of this text.
+    /// Labels in other texts are left out.
+    fn relative(&self, diagnostic: &Diagnostic) -> Diagnostic {
+        let end = self.offset + self.text.len();
+        let mut diagnostic = diagnostic.clone();
+        diagnostic
+            .secondary
+            .retain(|label| (self.offset..=end).contains(&label.span.start));
+        let offset = -(self.offset as isize);
+        diagnostic.primary.span = shift(diagnostic.primary.span, offset);
+        for label in &mut diagnostic.secondary {
+            label.span = shift(label.span, offset);
         }
+        diagnostic
     }
+}
 
-    pub fn error(line: u64, message: &str) {
-        Lox::report(line, "", message)
+fn shift(span: Span, offset: isize) -> Span {
+    Span {
+        start: span.start.saturating_add_signed(offset),
+        end: span.end.saturating_add_signed(offset),
+        ..span
     }
+}
+
+/// One sink handed to both backends, so `set_output` needs only one writer.
+#[derive(Clone)]
+struct SharedOutput(Rc<RefCell<Box<dyn Write>>>);
 
-    fn report(line: u64, _wh: &str, message: &str) {
-        eprintln!("[line {}] Error: {}", line, message)
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
     }
 
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+fn print_json(json: Result<String, String>) -> i32 {
+    match json {
+        Ok(json) => {
+            println!("{}", json);
+            EX_OK
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            EX_SOFTWARE
+        }
+    }
 }
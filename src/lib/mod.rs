@@ -1,9 +1,21 @@
 pub mod lox;
+pub mod repl;
 pub mod token_type;
 pub mod token;
 pub mod scanner;
 pub mod parser;
 pub mod ast_printer;
+pub mod dot_printer;
 pub mod interpreter;
 pub mod errors;
-pub mod env;
\ No newline at end of file
+pub mod env;
+pub mod value;
+pub mod callable;
+pub mod class;
+pub mod natives;
+pub mod resolver;
+pub mod chunk;
+pub mod compiler;
+pub mod vm;
+pub mod diagnostics;
+pub mod dump;
\ No newline at end of file
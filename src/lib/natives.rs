@@ -0,0 +1,95 @@
+use std::{
+    fmt,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    callable::LoxCallable,
+    errors::RuntimeError,
+    interpreter::{Interpreter, RuntimeResult},
+    token::Token,
+    value::Value,
+};
+
+/// The Rust side of a native function. Returning `Err` raises a runtime
+/// error with that message at the call site.
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A function implemented in Rust and exposed to Lox code as a global.
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runs the Rust side directly, for the VM, which has no interpreter to
+    /// pass. The caller has already checked the arity.
+    pub fn invoke(&self, arguments: &[Value]) -> Result<Value, String> {
+        (self.function)(arguments)
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        self: Rc<Self>,
+        _interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> RuntimeResult {
+        self.invoke(&arguments).map_err(|message| RuntimeError::new(paren, &message))
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// The natives every interpreter and VM starts with.
+pub fn builtins() -> Vec<Rc<NativeFunction>> {
+    vec![Rc::new(NativeFunction::new("clock", 0, clock))]
+}
+
+fn clock(_arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(seconds_since_epoch()?))
+}
+
+/// Seconds since the Unix epoch, with a fractional part.
+fn seconds_since_epoch() -> Result<f64, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?;
+    Ok(now.as_secs_f64())
+}
@@ -1,4 +1,14 @@
-use crate::{token::Token, token_type::TokenType};
+use std::{
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    errors::ParseError,
+    token::{Span, Token},
+    token_type::TokenType,
+    value::Value,
+};
 use strum::Display;
 
 pub trait ExprVisitor<T> {
@@ -8,7 +18,21 @@ pub trait ExprVisitor<T> {
     fn visit_unary(&mut self, e: &Expr) -> T;
     fn visit_print(&mut self, e: &StmtExpr) -> T;
     fn visit_expression(&mut self, e: &StmtExpr) -> T;
-    fn visit_var(&mut self, e: &VariableExpr) -> T;
+    fn visit_var(&mut self, e: &StmtExpr) -> T;
+    fn visit_block(&mut self, e: &StmtExpr) -> T;
+    fn visit_variable(&mut self, e: &Expr) -> T;
+    fn visit_assign(&mut self, e: &Expr) -> T;
+    fn visit_if(&mut self, e: &StmtExpr) -> T;
+    fn visit_while(&mut self, e: &StmtExpr) -> T;
+    fn visit_logical(&mut self, e: &Expr) -> T;
+    fn visit_call(&mut self, e: &Expr) -> T;
+    fn visit_function(&mut self, e: &StmtExpr) -> T;
+    fn visit_return(&mut self, e: &StmtExpr) -> T;
+    fn visit_class(&mut self, e: &StmtExpr) -> T;
+    fn visit_get(&mut self, e: &Expr) -> T;
+    fn visit_set(&mut self, e: &Expr) -> T;
+    fn visit_this(&mut self, e: &Expr) -> T;
+    fn visit_super(&mut self, e: &Expr) -> T;
 }
 
 pub trait Visitor<T> {
@@ -16,66 +40,162 @@ pub trait Visitor<T> {
 }
 
 #[derive(Debug, Display, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "expr"))]
 pub enum Expr {
     Binary(Box<BinaryExpr>),
     Grouping(Box<GroupingExpr>),
     Literal(LiteralExpr),
     Unary(Box<UnaryExpr>),
     Stmt(Box<StmtExpr>),
-    Variable(Box<VariableExpr>)
+    Variable(Box<VariableExpr>),
+    Assign(Box<AssignExpr>),
+    Logical(Box<LogicalExpr>),
+    Call(Box<CallExpr>),
+    Get(Box<GetExpr>),
+    Set(Box<SetExpr>),
+    This(Box<ThisExpr>),
+    Super(Box<SuperExpr>),
 }
 #[derive(Debug, Display, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "stmt"))]
 pub enum StmtExpr {
     Expression {
         expression: Expr,
+        span: Span,
     },
     Print {
         expression: Expr,
+        span: Span,
     },
     Var {
         name: Token,
         initializer: Option<Expr>,
+        span: Span,
+    },
+    Block {
+        statements: Vec<StmtExpr>,
+        span: Span,
+    },
+    If {
+        condition: Expr,
+        then_branch: Box<StmtExpr>,
+        else_branch: Option<Box<StmtExpr>>,
+        span: Span,
+    },
+    While {
+        condition: Expr,
+        body: Box<StmtExpr>,
+        span: Span,
+    },
+    Function(Rc<FunctionStmt>),
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+        span: Span,
+    },
+    Class {
+        name: Token,
+        superclass: Option<VariableExpr>,
+        methods: Vec<Rc<FunctionStmt>>,
+        span: Span,
     },
 }
 
+/// A function declaration. It is reference counted because every closure
+/// created from it shares the same parameter list and body.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FunctionStmt {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<StmtExpr>,
+    pub span: Span,
+}
+
 impl StmtExpr {
     pub fn get_inner(&self) -> Expr {
+        let nil = || {
+            Expr::Literal(LiteralExpr {
+                value: Value::Nil,
+                span: self.span(),
+            })
+        };
         match self {
-            StmtExpr::Expression { expression } => expression.clone(),
-            StmtExpr::Print { expression } => expression.clone(),
-            StmtExpr::Var { initializer, .. } => initializer.clone().unwrap_or_else(|| {
-                Expr::Literal(LiteralExpr {
-                    value: Some("null".to_string()),
-                })
-            }),
+            StmtExpr::Expression { expression, .. } => expression.clone(),
+            StmtExpr::Print { expression, .. } => expression.clone(),
+            StmtExpr::Var { initializer, .. } => initializer.clone().unwrap_or_else(nil),
+            StmtExpr::Block { .. } => nil(),
+            StmtExpr::If { condition, .. } => condition.clone(),
+            StmtExpr::While { condition, .. } => condition.clone(),
+            StmtExpr::Function(_) => nil(),
+            StmtExpr::Return { value, .. } => value.clone().unwrap_or_else(nil),
+            StmtExpr::Class { .. } => nil(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            StmtExpr::Expression { span, .. }
+            | StmtExpr::Print { span, .. }
+            | StmtExpr::Var { span, .. }
+            | StmtExpr::Block { span, .. }
+            | StmtExpr::If { span, .. }
+            | StmtExpr::While { span, .. }
+            | StmtExpr::Return { span, .. }
+            | StmtExpr::Class { span, .. } => *span,
+            StmtExpr::Function(function) => function.span,
         }
     }
 }
 
-impl Visitor<Option<String>> for Expr {
-    fn accept(&self, e: &mut dyn ExprVisitor<Option<String>>) -> Option<String> {
+impl Expr {
+    pub fn span(&self) -> Span {
         match self {
+            Expr::Binary(e) => e.span,
+            Expr::Grouping(e) => e.span,
+            Expr::Literal(e) => e.span,
+            Expr::Unary(e) => e.span,
+            Expr::Stmt(e) => e.span(),
+            Expr::Variable(e) => e.span,
+            Expr::Assign(e) => e.span,
+            Expr::Logical(e) => e.span,
+            Expr::Call(e) => e.span,
+            Expr::Get(e) => e.span,
+            Expr::Set(e) => e.span,
+            Expr::This(e) => e.span,
+            Expr::Super(e) => e.span,
+        }
+    }
+}
 
+impl<T> Visitor<T> for Expr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        match self {
             Expr::Binary(_) => e.visit_binary(self),
             Expr::Grouping(_) => e.visit_grouping(self),
             Expr::Literal(_) => e.visit_literal(self),
             Expr::Unary(_) => e.visit_unary(self),
-            Expr::Stmt(s) => {
-                let s = *s.clone();
-                match &s {
-                    StmtExpr::Expression { .. } => e.visit_expression(&s),
-                    StmtExpr::Print { .. } => e.visit_print(&s),
-                    StmtExpr::Var { .. } => e.visit_expression(&s),
-                }
-            },
-            Expr::Variable(_) => e.visit_literal(self)
+            Expr::Stmt(s) => s.accept(e),
+            Expr::Variable(_) => e.visit_variable(self),
+            Expr::Assign(_) => e.visit_assign(self),
+            Expr::Logical(_) => e.visit_logical(self),
+            Expr::Call(_) => e.visit_call(self),
+            Expr::Get(_) => e.visit_get(self),
+            Expr::Set(_) => e.visit_set(self),
+            Expr::This(_) => e.visit_this(self),
+            Expr::Super(_) => e.visit_super(self),
         }
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct VariableExpr {
+    /// Identifies this reference for the resolver; see [`next_expr_id`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub id: usize,
     pub name: Token,
+    pub span: Span,
 }
 
 impl VariableExpr {
@@ -85,95 +205,318 @@ impl VariableExpr {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AssignExpr {
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub id: usize,
+    pub name: Token,
+    pub value: Expr,
+    pub span: Span,
+}
+
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Hands out a unique id for expressions that refer to a variable. The
+/// resolver records scope depths by id, and ids stay unique across parsers
+/// so one interpreter can run code from many parses (e.g. REPL lines).
+pub fn next_expr_id() -> usize {
+    NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct BinaryExpr {
     pub left: Expr,
     pub operator: Token,
     pub right: Expr,
+    pub span: Span,
 }
 
-impl Visitor<Option<String>> for BinaryExpr {
-    fn accept(&self, e: &mut dyn ExprVisitor<Option<String>>) -> Option<String> {
+impl<T> Visitor<T> for BinaryExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
         e.visit_binary(&Expr::Binary(Box::new(self.clone())))
     }
 }
 
-impl Visitor<Option<String>> for LiteralExpr {
-    fn accept(&self, e: &mut dyn ExprVisitor<Option<String>>) -> Option<String> {
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LogicalExpr {
+    pub left: Expr,
+    pub operator: Token,
+    pub right: Expr,
+    pub span: Span,
+}
+
+impl<T> Visitor<T> for LogicalExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        e.visit_logical(&Expr::Logical(Box::new(self.clone())))
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CallExpr {
+    pub callee: Expr,
+    /// The closing parenthesis, kept to report errors at the call site.
+    pub paren: Token,
+    pub arguments: Vec<Expr>,
+    pub span: Span,
+}
+
+impl<T> Visitor<T> for CallExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        e.visit_call(&Expr::Call(Box::new(self.clone())))
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GetExpr {
+    pub object: Expr,
+    pub name: Token,
+    pub span: Span,
+}
+
+impl<T> Visitor<T> for GetExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        e.visit_get(&Expr::Get(Box::new(self.clone())))
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SetExpr {
+    pub object: Expr,
+    pub name: Token,
+    pub value: Expr,
+    pub span: Span,
+}
+
+impl<T> Visitor<T> for SetExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        e.visit_set(&Expr::Set(Box::new(self.clone())))
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ThisExpr {
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub id: usize,
+    pub keyword: Token,
+    pub span: Span,
+}
+
+impl<T> Visitor<T> for ThisExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        e.visit_this(&Expr::This(Box::new(self.clone())))
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SuperExpr {
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub id: usize,
+    pub keyword: Token,
+    pub method: Token,
+    pub span: Span,
+}
+
+impl<T> Visitor<T> for SuperExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        e.visit_super(&Expr::Super(Box::new(self.clone())))
+    }
+}
+
+impl<T> Visitor<T> for LiteralExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
         e.visit_literal(&Expr::Literal(self.clone()))
     }
 }
 
-impl Visitor<Option<String>> for GroupingExpr {
-    fn accept(&self, e: &mut dyn ExprVisitor<Option<String>>) -> Option<String> {
+impl<T> Visitor<T> for GroupingExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
         e.visit_grouping(&Expr::Grouping(Box::new(self.clone())))
     }
 }
 
-impl Visitor<Option<String>> for UnaryExpr {
-    fn accept(&self, e: &mut dyn ExprVisitor<Option<String>>) -> Option<String> {
+impl<T> Visitor<T> for UnaryExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
         e.visit_unary(&Expr::Unary(Box::new(self.clone())))
     }
 }
 
-impl Visitor<Option<String>> for StmtExpr {
-    fn accept(&self, e: &mut dyn ExprVisitor<Option<String>>) -> Option<String> {
-        return match self {
+impl<T> Visitor<T> for StmtExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        match self {
             StmtExpr::Expression { .. } => e.visit_expression(self),
             StmtExpr::Print { .. } => e.visit_print(self),
-            StmtExpr::Var { .. } => e.visit_expression(self),
-        };
+            StmtExpr::Var { .. } => e.visit_var(self),
+            StmtExpr::Block { .. } => e.visit_block(self),
+            StmtExpr::If { .. } => e.visit_if(self),
+            StmtExpr::While { .. } => e.visit_while(self),
+            StmtExpr::Function(_) => e.visit_function(self),
+            StmtExpr::Return { .. } => e.visit_return(self),
+            StmtExpr::Class { .. } => e.visit_class(self),
+        }
+    }
+}
+
+impl<T> Visitor<T> for VariableExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        e.visit_variable(&Expr::Variable(Box::new(self.clone())))
     }
 }
 
-impl Visitor<Option<String>> for VariableExpr {
-    fn accept(&self, e: &mut dyn ExprVisitor<Option<String>>) -> Option<String> {
-        e.visit_literal(&Expr::Variable(Box::new(self.clone())))
+impl<T> Visitor<T> for AssignExpr {
+    fn accept(&self, e: &mut dyn ExprVisitor<T>) -> T {
+        e.visit_assign(&Expr::Assign(Box::new(self.clone())))
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct GroupingExpr {
     pub expression: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct LiteralExpr {
-    pub value: Option<String>,
+    pub value: Value,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct UnaryExpr {
     pub operator: Token,
     pub right: Expr,
+    pub span: Span,
 }
 
+/// How deeply expressions and statements may nest before parsing fails,
+/// well before the recursion could overflow the stack.
+const MAX_NESTING: usize = 256;
+
 #[derive(Debug, Clone)]
 pub struct Parser {
     current: u64,
     pub tokens: Vec<Token>,
+    errors: Vec<ParseError>,
+    /// Expressions and statements currently being parsed inside each other.
+    depth: usize,
 }
 
+type ParseResult<T> = Result<T, ParseError>;
+
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { current: 0, tokens }
+        Self {
+            current: 0,
+            tokens,
+            errors: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn expression(&mut self) -> ParseResult<Expr> {
+        self.nested(Self::assignment)
+    }
+
+    /// Runs `parse` one level deeper, failing once nesting gets too deep.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        if self.depth == MAX_NESTING {
+            return Err(ParseError::new(&self.peek(), "Too much nesting."));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn assignment(&mut self) -> ParseResult<Expr> {
+        let expr = self.or()?;
+        if self.match_next(&[TokenType::Equal]) {
+            let equals = self.previous();
+            // Assignment is right-associative, so recurse instead of looping.
+            let value = self.nested(Self::assignment)?;
+            match expr {
+                Expr::Variable(v) => {
+                    let span = v.span.to(value.span());
+                    return Ok(Expr::Assign(Box::new(AssignExpr {
+                        id: next_expr_id(),
+                        name: v.name,
+                        value,
+                        span,
+                    })));
+                }
+                Expr::Get(g) => {
+                    let span = g.span.to(value.span());
+                    return Ok(Expr::Set(Box::new(SetExpr {
+                        object: g.object,
+                        name: g.name,
+                        value,
+                        span,
+                    })));
+                }
+                _ => (),
+            }
+            // The parser is not confused here, so report without unwinding.
+            self.errors
+                .push(ParseError::new(&equals, "Invalid assignment target."));
+        }
+        Ok(expr)
     }
 
-    fn expression(&mut self) -> Expr {
-        self.equality()
+    fn or(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.and()?;
+        while self.match_next(&[TokenType::Or]) {
+            let op = self.previous();
+            let right = self.and()?;
+            let span = expr.span().to(right.span());
+            expr = Expr::Logical(Box::new(LogicalExpr {
+                left: expr,
+                operator: op,
+                right,
+                span,
+            }));
+        }
+        Ok(expr)
     }
 
-    fn equality(&mut self) -> Expr {
-        let mut expr = self.comparison();
+    fn and(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.equality()?;
+        while self.match_next(&[TokenType::And]) {
+            let op = self.previous();
+            let right = self.equality()?;
+            let span = expr.span().to(right.span());
+            expr = Expr::Logical(Box::new(LogicalExpr {
+                left: expr,
+                operator: op,
+                right,
+                span,
+            }));
+        }
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.comparison()?;
         while self.match_next(&[TokenType::BangEqual, TokenType::EqualEqual]) {
             let op = self.previous();
-            let right = self.comparison();
+            let right = self.comparison()?;
+            let span = expr.span().to(right.span());
             expr = Expr::Binary(Box::new(BinaryExpr {
                 left: expr,
                 operator: op,
                 right,
+                span,
             }));
         }
-        expr
+        Ok(expr)
     }
 
     fn match_next(&mut self, types: &[TokenType]) -> bool {
@@ -213,8 +556,8 @@ impl Parser {
         self.tokens[i as usize].clone()
     }
 
-    fn comparison(&mut self) -> Expr {
-        let mut expr = self.term();
+    fn comparison(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.term()?;
         let l = &[
             TokenType::Greater,
             TokenType::GreaterEqual,
@@ -223,147 +566,495 @@ impl Parser {
         ];
         while self.match_next(l) {
             let op = self.previous();
-            let right = self.term();
+            let right = self.term()?;
+            let span = expr.span().to(right.span());
             expr = Expr::Binary(Box::new(BinaryExpr {
                 left: expr,
                 operator: op,
                 right,
+                span,
             }));
         }
-        expr
+        Ok(expr)
     }
 
-    fn term(&mut self) -> Expr {
-        let mut expr = self.factor();
+    fn term(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.factor()?;
         let l = [TokenType::Minus, TokenType::Plus];
         while self.match_next(&l) {
             let op = self.previous();
-            let right = self.factor();
+            let right = self.factor()?;
+            let span = expr.span().to(right.span());
             expr = Expr::Binary(Box::new(BinaryExpr {
                 left: expr,
                 operator: op,
                 right,
+                span,
             }));
         }
-        expr
+        Ok(expr)
     }
 
-    fn factor(&mut self) -> Expr {
-        let mut expr = self.unary();
+    fn factor(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.unary()?;
         let l = [TokenType::Slash, TokenType::Star];
         while self.match_next(&l) {
             let op = self.previous();
-            let right = self.unary();
+            let right = self.unary()?;
+            let span = expr.span().to(right.span());
             expr = Expr::Binary(Box::new(BinaryExpr {
                 left: expr,
                 operator: op,
                 right,
+                span,
             }));
         }
-        expr
+        Ok(expr)
     }
 
-    fn unary(&mut self) -> Expr {
+    fn unary(&mut self) -> ParseResult<Expr> {
         if self.match_next(&[TokenType::Bang, TokenType::Minus]) {
             let op = self.previous();
-            let right = self.unary();
-            return Expr::Unary(Box::new(UnaryExpr {
+            let right = self.nested(Self::unary)?;
+            let span = op.span.to(right.span());
+            return Ok(Expr::Unary(Box::new(UnaryExpr {
                 operator: op,
                 right,
-            }));
+                span,
+            })));
+        }
+        self.call()
+    }
+
+    fn call(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.match_next(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_next(&[TokenType::Dot]) {
+                let name =
+                    self.consume(TokenType::Indentifier, "Expect property name after '.'.")?;
+                let span = expr.span().to(name.span);
+                expr = Expr::Get(Box::new(GetExpr {
+                    object: expr,
+                    name,
+                    span,
+                }));
+            } else {
+                break;
+            }
         }
-        self.primary().unwrap()
+        Ok(expr)
     }
 
-    fn primary(&mut self) -> Option<Expr> {
+    fn finish_call(&mut self, callee: Expr) -> ParseResult<Expr> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    let token = self.peek();
+                    self.errors.push(ParseError::new(
+                        &token,
+                        "Can't have more than 255 arguments.",
+                    ));
+                }
+                arguments.push(self.expression()?);
+                if !self.match_next(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+        let span = callee.span().to(paren.span);
+        Ok(Expr::Call(Box::new(CallExpr {
+            callee,
+            paren,
+            arguments,
+            span,
+        })))
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
         if self.match_next(&[TokenType::False]) {
-            return Some(Expr::Literal(LiteralExpr {
-                value: Some("false".to_string()),
+            return Ok(Expr::Literal(LiteralExpr {
+                value: Value::Bool(false),
+                span: self.previous().span,
             }));
         }
 
         if self.match_next(&[TokenType::True]) {
-            return Some(Expr::Literal(LiteralExpr {
-                value: Some("true".to_string()),
+            return Ok(Expr::Literal(LiteralExpr {
+                value: Value::Bool(true),
+                span: self.previous().span,
             }));
         }
+        if self.match_next(&[TokenType::Super]) {
+            let keyword = self.previous();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self.consume(TokenType::Indentifier, "Expect superclass method name.")?;
+            let span = keyword.span.to(method.span);
+            return Ok(Expr::Super(Box::new(SuperExpr {
+                id: next_expr_id(),
+                keyword,
+                method,
+                span,
+            })));
+        }
+        if self.match_next(&[TokenType::This]) {
+            let keyword = self.previous();
+            return Ok(Expr::This(Box::new(ThisExpr {
+                id: next_expr_id(),
+                span: keyword.span,
+                keyword,
+            })));
+        }
         if self.match_next(&[TokenType::Indentifier]) {
-            return Some(Expr::Variable(Box::new(VariableExpr {
-                name: self.previous(),
+            let name = self.previous();
+            return Ok(Expr::Variable(Box::new(VariableExpr {
+                id: next_expr_id(),
+                span: name.span,
+                name,
             })));
         }
         if self.match_next(&[TokenType::Nil]) {
-            return Some(Expr::Literal(LiteralExpr {
-                value: Some("null".to_string()),
+            return Ok(Expr::Literal(LiteralExpr {
+                value: Value::Nil,
+                span: self.previous().span,
+            }));
+        }
+
+        if self.match_next(&[TokenType::Number]) {
+            let token = self.previous();
+            let literal = token.literal.clone().unwrap_or_default();
+            let value = literal
+                .parse()
+                .map_err(|_| ParseError::new(&token, "Invalid number literal."))?;
+            return Ok(Expr::Literal(LiteralExpr {
+                value: Value::Number(value),
+                span: token.span,
             }));
         }
 
-        if self.match_next(&[TokenType::Number, TokenType::String]) {
-            return Some(Expr::Literal(LiteralExpr {
-                value: self.previous().literal,
+        if self.match_next(&[TokenType::String]) {
+            let token = self.previous();
+            return Ok(Expr::Literal(LiteralExpr {
+                value: Value::String(token.literal.unwrap_or_default()),
+                span: token.span,
             }));
         }
 
         if self.match_next(&[TokenType::LeftParen]) {
-            let expr = self.expression();
-            self.consume(TokenType::RightParen, "Expect ')' after expression");
-            return Some(Expr::Grouping(Box::new(GroupingExpr { expression: expr })));
+            let start = self.previous().span;
+            let expr = self.expression()?;
+            self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+            return Ok(Expr::Grouping(Box::new(GroupingExpr {
+                expression: expr,
+                span: self.span_from(start),
+            })));
         }
 
-        None
+        Err(ParseError::new(&self.peek(), "Expect expression."))
+    }
+    fn expression_statement(&mut self) -> ParseResult<StmtExpr> {
+        let expr = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(StmtExpr::Expression {
+            span: self.span_from(expr.span()),
+            expression: expr,
+        })
     }
-    fn expression_statement(&mut self) -> StmtExpr {
-        let expr = self.expression();
-        self.consume(TokenType::Semicolon, "Expect ';' after value");
-        StmtExpr::Expression { expression: expr }
+    fn print_statement(&mut self) -> ParseResult<StmtExpr> {
+        let start = self.previous().span;
+        let expr = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        Ok(StmtExpr::Print {
+            expression: expr,
+            span: self.span_from(start),
+        })
     }
-    fn print_statement(&mut self) -> StmtExpr {
-        let expr = self.expression();
-        self.consume(TokenType::Semicolon, "Expect ';' after expression");
-        StmtExpr::Print { expression: expr }
+
+    fn statement(&mut self) -> ParseResult<StmtExpr> {
+        self.nested(Self::unnested_statement)
     }
 
-    fn statement(&mut self) -> StmtExpr {
+    fn unnested_statement(&mut self) -> ParseResult<StmtExpr> {
+        if self.match_next(&[TokenType::For]) {
+            return self.for_statement();
+        }
+        if self.match_next(&[TokenType::If]) {
+            return self.if_statement();
+        }
         if self.match_next(&[TokenType::Print]) {
             return self.print_statement();
         }
+        if self.match_next(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.match_next(&[TokenType::While]) {
+            return self.while_statement();
+        }
+        if self.match_next(&[TokenType::LeftBrace]) {
+            let start = self.previous().span;
+            return Ok(StmtExpr::Block {
+                statements: self.block()?,
+                span: self.span_from(start),
+            });
+        }
         self.expression_statement()
     }
 
-    fn declaration(&mut self) -> StmtExpr {
+    fn return_statement(&mut self) -> ParseResult<StmtExpr> {
+        let keyword = self.previous();
+        let mut value = None;
+        if !self.check(TokenType::Semicolon) {
+            value = Some(self.expression()?);
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(StmtExpr::Return {
+            span: self.span_from(keyword.span),
+            keyword,
+            value,
+        })
+    }
+
+    fn if_statement(&mut self) -> ParseResult<StmtExpr> {
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+        let then_branch = Box::new(self.statement()?);
+        // An `else` binds to the nearest `if`, which is the one we are in.
+        let mut else_branch = None;
+        if self.match_next(&[TokenType::Else]) {
+            else_branch = Some(Box::new(self.statement()?));
+        }
+        Ok(StmtExpr::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: self.span_from(start),
+        })
+    }
+
+    fn while_statement(&mut self) -> ParseResult<StmtExpr> {
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.statement()?);
+        Ok(StmtExpr::While {
+            condition,
+            body,
+            span: self.span_from(start),
+        })
+    }
+
+    /// `for` has no node of its own; it is desugared into a `while` loop
+    /// wrapped in blocks for the initializer and increment.
+    fn for_statement(&mut self) -> ParseResult<StmtExpr> {
+        let start = self.previous().span;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        let initializer = if self.match_next(&[TokenType::Semicolon]) {
+            None
+        } else if self.match_next(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(TokenType::Semicolon) {
+            Expr::Literal(LiteralExpr {
+                value: Value::Bool(true),
+                span: self.peek().span,
+            })
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let mut increment = None;
+        if !self.check(TokenType::RightParen) {
+            increment = Some(self.expression()?);
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let mut body = self.statement()?;
+        // Every desugared node covers the whole `for` statement.
+        let span = self.span_from(start);
+        if let Some(increment) = increment {
+            body = StmtExpr::Block {
+                statements: vec![
+                    body,
+                    StmtExpr::Expression {
+                        span: increment.span(),
+                        expression: increment,
+                    },
+                ],
+                span,
+            };
+        }
+        body = StmtExpr::While {
+            condition,
+            body: Box::new(body),
+            span,
+        };
+        if let Some(initializer) = initializer {
+            body = StmtExpr::Block {
+                statements: vec![initializer, body],
+                span,
+            };
+        }
+        Ok(body)
+    }
+
+    fn block(&mut self) -> ParseResult<Vec<StmtExpr>> {
+        let mut statements = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            statements.push(self.declaration()?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
+    fn declaration(&mut self) -> ParseResult<StmtExpr> {
+        if self.match_next(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        if self.match_next(&[TokenType::Fun]) {
+            return Ok(StmtExpr::Function(Rc::new(self.function("function")?)));
+        }
         if self.match_next(&[TokenType::Var]) {
             return self.var_declaration();
         }
         self.statement()
     }
-    fn var_declaration(&mut self) -> StmtExpr {
-        let name = self.consume(TokenType::Indentifier, "Expect variable name");
+    fn class_declaration(&mut self) -> ParseResult<StmtExpr> {
+        let start = self.previous().span;
+        let name = self.consume(TokenType::Indentifier, "Expect class name.")?;
+        let mut superclass = None;
+        if self.match_next(&[TokenType::Less]) {
+            let name = self.consume(TokenType::Indentifier, "Expect superclass name.")?;
+            superclass = Some(VariableExpr {
+                id: next_expr_id(),
+                span: name.span,
+                name,
+            });
+        }
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(Rc::new(self.function("method")?));
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(StmtExpr::Class {
+            name,
+            superclass,
+            methods,
+            span: self.span_from(start),
+        })
+    }
+
+    fn function(&mut self, kind: &str) -> ParseResult<FunctionStmt> {
+        let name = self.consume(TokenType::Indentifier, &format!("Expect {} name.", kind))?;
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    let token = self.peek();
+                    self.errors.push(ParseError::new(
+                        &token,
+                        "Can't have more than 255 parameters.",
+                    ));
+                }
+                params.push(self.consume(TokenType::Indentifier, "Expect parameter name.")?);
+                if !self.match_next(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        let body = self.nested(Self::block)?;
+        Ok(FunctionStmt {
+            span: self.span_from(name.span),
+            name,
+            params,
+            body,
+        })
+    }
+
+    fn var_declaration(&mut self) -> ParseResult<StmtExpr> {
+        let start = self.previous().span;
+        let name = self.consume(TokenType::Indentifier, "Expect variable name.")?;
         let mut initializer = None;
         if self.match_next(&[TokenType::Equal]) {
-            initializer = Some(self.expression());
+            initializer = Some(self.expression()?);
         }
         self.consume(
             TokenType::Semicolon,
-            "Expect ';' after variable declaration",
-        );
-        StmtExpr::Var { name, initializer }
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(StmtExpr::Var {
+            name,
+            initializer,
+            span: self.span_from(start),
+        })
     }
-    fn consume(&mut self, tty: TokenType, message: &str) -> Token {
+    /// Span from `start` through the most recently consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span)
+    }
+
+    fn consume(&mut self, tty: TokenType, message: &str) -> ParseResult<Token> {
         if self.check(tty) {
-            return self.advance();
+            return Ok(self.advance());
         }
-        panic!("{}", message)
+        Err(ParseError::new(&self.peek(), message))
     }
 
-    pub fn parse(&mut self) -> Vec<StmtExpr> {
+    /// Parses the token stream as one bare expression, as typed at the REPL.
+    /// Returns `None` if it is not one: it does not start like an
+    /// expression, or more input follows a complete one. Either way it may
+    /// still parse as statements. Errors past the first token are returned.
+    pub fn parse_expression(&mut self) -> ParseResult<Option<Expr>> {
+        let first = self.peek();
+        let expr = match self.expression() {
+            Ok(expr) => expr,
+            Err(error) if error.token.span == first.span => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        if let Some(error) = self.errors.drain(..).next() {
+            return Err(error);
+        }
+        if !self.is_at_end() {
+            return Ok(None);
+        }
+        Ok(Some(expr))
+    }
+
+    /// Parses the whole token stream. A syntax error does not stop parsing:
+    /// the parser resynchronizes at the next statement boundary and keeps
+    /// going, so every error in the file is returned alongside the
+    /// statements that did parse.
+    pub fn parse(&mut self) -> (Vec<StmtExpr>, Vec<ParseError>) {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration());
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(error) => {
+                    self.errors.push(error);
+                    self.synchronize();
+                }
+            }
         }
-        println!("{:#?}", statements);
-        statements
-        // self.expression()
-        
+        (statements, std::mem::take(&mut self.errors))
     }
 
     pub fn synchronize(&mut self) {
@@ -374,17 +1065,88 @@ impl Parser {
             }
 
             match self.peek().tty {
-                TokenType::Class => (),
-                TokenType::Fun => (),
-                TokenType::Var => (),
-                TokenType::For => (),
-                TokenType::If => (),
-                TokenType::While => (),
-                TokenType::Print => (),
-                TokenType::Return => (),
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
                 _ => (),
             }
+            self.advance();
         }
-        self.advance();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    /// Each error's message, line and the text it points at.
+    fn parse_errors(source: &str) -> (usize, Vec<(String, u64, String)>) {
+        let tokens = Scanner::new(source).scan_tokens();
+        let (statements, errors) = Parser::new(tokens).parse();
+        let errors = errors
+            .into_iter()
+            .map(|error| (error.message, error.token.span.line, error.token.lexeme))
+            .collect();
+        (statements.len(), errors)
+    }
+
+    fn error(message: &str, line: u64, lexeme: &str) -> (String, u64, String) {
+        (message.to_string(), line, lexeme.to_string())
+    }
+
+    #[test]
+    fn reports_every_error_and_keeps_the_rest() {
+        let (statements, errors) = parse_errors(
+            "var = 1;
+             print 1;
+             fun f( { }
+             print 2 +;
+             1 = 2;
+             class A { m() { return 1 } }
+             print 3;",
+        );
+        assert_eq!(
+            errors,
+            [
+                error("Expect variable name.", 1, "="),
+                error("Expect parameter name.", 3, "{"),
+                error("Expect expression.", 4, ";"),
+                error("Invalid assignment target.", 5, "="),
+                error("Expect ';' after return value.", 6, "}"),
+            ]
+        );
+        // `print 1;`, `1 = 2;` and `print 3;`.
+        assert_eq!(statements, 3);
+    }
+
+    #[test]
+    fn recovers_at_statement_keywords() {
+        let (statements, errors) = parse_errors("var x = 1 + * 2 class A {} print x;");
+        assert_eq!(errors, [error("Expect expression.", 1, "*")]);
+        assert_eq!(statements, 2);
+    }
+
+    #[test]
+    fn too_much_nesting() {
+        let too_deep = [
+            format!("{}1;", "-".repeat(MAX_NESTING + 1)),
+            format!("var a; a = {}1;", "a = ".repeat(MAX_NESTING + 1)),
+            format!("{}{}", "{".repeat(MAX_NESTING + 1), "}".repeat(MAX_NESTING + 1)),
+        ];
+        for source in &too_deep {
+            let (_, errors) = parse_errors(source);
+            assert_eq!(errors.len(), 1, "{}", source);
+            assert_eq!(errors[0].0, "Too much nesting.");
+        }
+        // Each block is one level.
+        let deepest = format!("{}{}", "{".repeat(MAX_NESTING), "}".repeat(MAX_NESTING));
+        let (_, errors) = parse_errors(&deepest);
+        assert!(errors.is_empty());
     }
 }
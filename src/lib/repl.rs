@@ -0,0 +1,241 @@
+use std::path::PathBuf;
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{
+    ast_printer::{print_program, AstPrinter, Notation},
+    lox::{Backend, Lox},
+    parser::{Parser, StmtExpr},
+};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+const HISTORY_FILE: &str = ".rlox_history";
+
+const HELP: &str = "\
+:tokens <code>  print the tokens scanned from <code>
+:ast <code>     print the syntax tree parsed from <code>
+:env            list global variables
+:reset          discard all definitions and start over
+:help           show this message
+:quit           leave the REPL (Ctrl-D works too)";
+
+/// Interactive session. Everything entered runs in the same interpreter, so
+/// definitions persist from one entry to the next.
+pub struct Repl<'a> {
+    lox: &'a mut Lox,
+    quit: bool,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(lox: &'a mut Lox) -> Self {
+        Self { lox, quit: false }
+    }
+
+    pub fn run(&mut self) {
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(error) => {
+                eprintln!("Could not start line editor: {}", error);
+                return;
+            }
+        };
+        let history = history_path();
+        if let Some(path) = &history {
+            // A missing history file just means this is the first session.
+            let _ = editor.load_history(path);
+        }
+
+        while !self.quit {
+            let Some(entry) = read_entry(&mut editor) else {
+                break;
+            };
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let _ = editor.add_history_entry(entry.trim_end());
+            // The newline that ended the entry is not part of it.
+            let entry = entry.strip_suffix('\n').unwrap_or(&entry);
+            if !self.meta_command(entry) {
+                self.eval(entry);
+            }
+            // A mistake in one entry should not end the session.
+            self.lox.reset_errors();
+        }
+
+        if let Some(path) = &history {
+            if let Err(error) = editor.save_history(path) {
+                eprintln!("Could not save history to {}: {}", path.display(), error);
+            }
+        }
+    }
+
+    /// Runs one entry. A bare expression has its value printed; anything
+    /// else runs as ordinary statements.
+    fn eval(&mut self, source: &str) {
+        let tokens = self.lox.scan(source);
+        if self.lox.had_error() {
+            return;
+        }
+        match Parser::new(tokens.clone()).parse_expression() {
+            Ok(Some(expression)) => {
+                let span = expression.span();
+                self.lox.execute(vec![StmtExpr::Print { expression, span }])
+            }
+            Ok(None) => self.lox.run_tokens(tokens),
+            Err(error) => self.lox.parse_error(&error),
+        }
+    }
+
+    /// `:ast`: like `eval`, takes a bare expression on its own and anything
+    /// else as statements.
+    fn print_ast(&mut self, source: &str) {
+        let tokens = self.lox.scan(source);
+        if self.lox.had_error() {
+            return;
+        }
+        match Parser::new(tokens.clone()).parse_expression() {
+            Ok(Some(expression)) => println!("{}", AstPrinter::new().print(&expression)),
+            Ok(None) => {
+                let (statements, errors) = Parser::new(tokens).parse();
+                for error in &errors {
+                    self.lox.parse_error(error);
+                }
+                if errors.is_empty() {
+                    println!("{}", print_program(&statements, Notation::SExpression));
+                }
+            }
+            Err(error) => self.lox.parse_error(&error),
+        }
+    }
+
+    /// Handles `:command` entries. Returns false if `entry` is Lox code.
+    fn meta_command(&mut self, entry: &str) -> bool {
+        let entry = entry.trim();
+        let Some(command) = entry.strip_prefix(':') else {
+            return false;
+        };
+        let (name, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        match name {
+            "tokens" => {
+                let tokens = self.lox.scan(argument);
+                if self.lox.had_error() {
+                    return true;
+                }
+                for token in tokens {
+                    println!("{}", token);
+                }
+            }
+            "ast" => self.print_ast(argument),
+            "env" => {
+                let mut values: Vec<(String, String)> = match self.lox.backend() {
+                    Backend::Interpreter => {
+                        let globals = self.lox.interpreter().globals();
+                        let globals = globals.borrow();
+                        globals
+                            .values()
+                            .map(|(name, value)| (name.clone(), value.to_string()))
+                            .collect()
+                    }
+                    Backend::Vm => self
+                        .lox
+                        .vm()
+                        .globals()
+                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                        .collect(),
+                };
+                values.sort();
+                for (name, value) in values {
+                    println!("{} = {}", name, value);
+                }
+            }
+            "reset" => {
+                self.lox.reset();
+                println!("Interpreter reset.");
+            }
+            "help" => println!("{}", HELP),
+            "quit" | "exit" => self.quit = true,
+            _ => eprintln!("Unknown command ':{}'. Try :help.", name),
+        }
+        true
+    }
+}
+
+/// Reads lines until brackets balance, so a block or call can span several
+/// lines. Returns `None` at end of input.
+fn read_entry(editor: &mut DefaultEditor) -> Option<String> {
+    let mut entry = String::new();
+    loop {
+        let prompt = if entry.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                entry.push_str(&line);
+                entry.push('\n');
+                if entry.trim_start().starts_with(':') || !is_incomplete(&entry) {
+                    return Some(entry);
+                }
+            }
+            // Ctrl-C abandons the current entry but keeps the session.
+            Err(ReadlineError::Interrupted) => entry.clear(),
+            Err(_) => return None,
+        }
+    }
+}
+
+/// True while `source` has more `(`/`{` than `)`/`}` outside of strings and
+/// comments.
+fn is_incomplete(source: &str) -> bool {
+    let mut depth = 0isize;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            '"' => loop {
+                match chars.next() {
+                    // Still inside a string literal.
+                    None => return true,
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some('"') => break,
+                    Some(_) => (),
+                }
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // Block comments nest.
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        None => return true,
+                        Some('/') if chars.peek() == Some(&'*') => {
+                            chars.next();
+                            depth += 1;
+                        }
+                        Some('*') if chars.peek() == Some(&'/') => {
+                            chars.next();
+                            depth -= 1;
+                        }
+                        Some(_) => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+fn history_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(HISTORY_FILE))
+}
+
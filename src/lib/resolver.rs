@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+use crate::{
+    diagnostics::Diagnostic,
+    errors::ParseError,
+    interpreter::Interpreter,
+    parser::{Expr, ExprVisitor, FunctionStmt, StmtExpr, Visitor},
+    token::{Span, Token},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// A name declared in a local scope.
+#[derive(Debug, Clone, Copy)]
+struct Local {
+    /// False while its initializer is being resolved.
+    defined: bool,
+    span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Static pass run between parsing and interpreting. It works out how many
+/// scopes separate each variable use from its declaration, hands that to the
+/// interpreter, and reports errors that need no runtime information.
+pub struct Resolver<'a> {
+    interpreter: &'a mut Interpreter,
+    /// One map per local scope, innermost last.
+    scopes: Vec<HashMap<String, Local>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        Self {
+            interpreter,
+            scopes: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Resolves a whole program, returning every static error found.
+    pub fn resolve(&mut self, statements: &[StmtExpr]) -> Vec<Diagnostic> {
+        self.resolve_statements(statements);
+        std::mem::take(&mut self.errors)
+    }
+
+    fn resolve_statements(&mut self, statements: &[StmtExpr]) {
+        for statement in statements {
+            statement.accept(self);
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn resolve_function(&mut self, function: &FunctionStmt, kind: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = kind;
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&function.body);
+        self.end_scope();
+        self.current_function = enclosing_function;
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.errors.push(Diagnostic::from(&ParseError::new(token, message)));
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if let Some(previous) = scope.get(&name.lexeme) {
+            let error = Diagnostic::from(&ParseError::new(
+                name,
+                "Already a variable with this name in this scope.",
+            ))
+            .with_secondary(previous.span, "previous declaration here");
+            self.errors.push(error);
+            return;
+        }
+        scope.insert(
+            name.lexeme.clone(),
+            Local {
+                defined: false,
+                span: name.span,
+            },
+        );
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            let local = scope.entry(name.lexeme.clone()).or_insert(Local {
+                defined: true,
+                span: name.span,
+            });
+            local.defined = true;
+        }
+    }
+
+    /// Defines a name the user never declares, such as `this`, attributing
+    /// it to `span`.
+    fn define_implicit(&mut self, name: &str, span: Span) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Local { defined: true, span });
+        }
+    }
+
+    fn resolve_local(&mut self, id: usize, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&name.lexeme) {
+                self.interpreter.resolve(id, depth);
+                return;
+            }
+        }
+        // Not found in any local scope: assume it is a global.
+    }
+}
+
+impl<'a> ExprVisitor<()> for Resolver<'a> {
+    fn visit_binary(&mut self, e: &Expr) {
+        if let Expr::Binary(b) = e {
+            self.resolve_expr(&b.left);
+            self.resolve_expr(&b.right);
+        }
+    }
+
+    fn visit_grouping(&mut self, e: &Expr) {
+        if let Expr::Grouping(g) = e {
+            self.resolve_expr(&g.expression);
+        }
+    }
+
+    fn visit_literal(&mut self, _e: &Expr) {}
+
+    fn visit_unary(&mut self, e: &Expr) {
+        if let Expr::Unary(u) = e {
+            self.resolve_expr(&u.right);
+        }
+    }
+
+    fn visit_print(&mut self, e: &StmtExpr) {
+        self.resolve_expr(&e.get_inner());
+    }
+
+    fn visit_expression(&mut self, e: &StmtExpr) {
+        self.resolve_expr(&e.get_inner());
+    }
+
+    fn visit_var(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Var { name, initializer, .. } = e {
+            self.declare(name);
+            if let Some(initializer) = initializer {
+                self.resolve_expr(initializer);
+            }
+            self.define(name);
+        }
+    }
+
+    fn visit_block(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Block { statements, .. } = e {
+            self.begin_scope();
+            self.resolve_statements(statements);
+            self.end_scope();
+        }
+    }
+
+    fn visit_variable(&mut self, e: &Expr) {
+        if let Expr::Variable(v) = e {
+            let declared_not_defined = self
+                .scopes
+                .last()
+                .and_then(|scope| scope.get(&v.name.lexeme))
+                .is_some_and(|local| !local.defined);
+            if declared_not_defined {
+                self.error(&v.name, "Can't read local variable in its own initializer.");
+            }
+            self.resolve_local(v.id, &v.name);
+        }
+    }
+
+    fn visit_assign(&mut self, e: &Expr) {
+        if let Expr::Assign(a) = e {
+            self.resolve_expr(&a.value);
+            self.resolve_local(a.id, &a.name);
+        }
+    }
+
+    fn visit_if(&mut self, e: &StmtExpr) {
+        if let StmtExpr::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } = e
+        {
+            self.resolve_expr(condition);
+            then_branch.accept(self);
+            if let Some(else_branch) = else_branch {
+                else_branch.accept(self);
+            }
+        }
+    }
+
+    fn visit_while(&mut self, e: &StmtExpr) {
+        if let StmtExpr::While { condition, body, .. } = e {
+            self.resolve_expr(condition);
+            body.accept(self);
+        }
+    }
+
+    fn visit_logical(&mut self, e: &Expr) {
+        if let Expr::Logical(l) = e {
+            self.resolve_expr(&l.left);
+            self.resolve_expr(&l.right);
+        }
+    }
+
+    fn visit_call(&mut self, e: &Expr) {
+        if let Expr::Call(c) = e {
+            self.resolve_expr(&c.callee);
+            for argument in &c.arguments {
+                self.resolve_expr(argument);
+            }
+        }
+    }
+
+    fn visit_function(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Function(function) = e {
+            // Define eagerly so the function can refer to itself recursively.
+            self.declare(&function.name);
+            self.define(&function.name);
+            self.resolve_function(function, FunctionType::Function);
+        }
+    }
+
+    fn visit_return(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Return { keyword, value, .. } = e {
+            if self.current_function == FunctionType::None {
+                self.error(keyword, "Can't return from top-level code.");
+            }
+            if let Some(value) = value {
+                if self.current_function == FunctionType::Initializer {
+                    self.error(keyword, "Can't return a value from an initializer.");
+                }
+                self.resolve_expr(value);
+            }
+        }
+    }
+
+    fn visit_class(&mut self, e: &StmtExpr) {
+        if let StmtExpr::Class {
+            name,
+            superclass,
+            methods,
+            ..
+        } = e
+        {
+            let enclosing_class = self.current_class;
+            self.current_class = ClassType::Class;
+            self.declare(name);
+            self.define(name);
+
+            if let Some(superclass) = superclass {
+                if superclass.name.lexeme == name.lexeme {
+                    self.error(&superclass.name, "A class can't inherit from itself.");
+                }
+                self.current_class = ClassType::Subclass;
+                superclass.accept(self);
+                self.begin_scope();
+                self.define_implicit("super", superclass.span);
+            }
+
+            self.begin_scope();
+            self.define_implicit("this", name.span);
+            for method in methods {
+                let kind = if method.name.lexeme == "init" {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+                self.resolve_function(method, kind);
+            }
+            self.end_scope();
+
+            if superclass.is_some() {
+                self.end_scope();
+            }
+            self.current_class = enclosing_class;
+        }
+    }
+
+    fn visit_get(&mut self, e: &Expr) {
+        if let Expr::Get(g) = e {
+            self.resolve_expr(&g.object);
+        }
+    }
+
+    fn visit_set(&mut self, e: &Expr) {
+        if let Expr::Set(s) = e {
+            self.resolve_expr(&s.value);
+            self.resolve_expr(&s.object);
+        }
+    }
+
+    fn visit_this(&mut self, e: &Expr) {
+        if let Expr::This(t) = e {
+            if self.current_class == ClassType::None {
+                self.error(&t.keyword, "Can't use 'this' outside of a class.");
+                return;
+            }
+            self.resolve_local(t.id, &t.keyword);
+        }
+    }
+
+    fn visit_super(&mut self, e: &Expr) {
+        if let Expr::Super(s) = e {
+            match self.current_class {
+                ClassType::None => {
+                    self.error(&s.keyword, "Can't use 'super' outside of a class.")
+                }
+                ClassType::Class => {
+                    let error = Diagnostic::from(&ParseError::new(
+                        &s.keyword,
+                        "Can't use 'super' in a class with no superclass.",
+                    ))
+                    .with_note("declare a superclass with 'class Name < Superclass'");
+                    self.errors.push(error);
+                }
+                ClassType::Subclass => self.resolve_local(s.id, &s.keyword),
+            }
+        }
+    }
+}
@@ -1,17 +1,25 @@
 use crate::{
-    lox::Lox,
-    token::{Token},
+    diagnostics::Diagnostic,
+    token::{Span, Token},
     token_type::TokenType,
 };
-use std::{collections::HashMap};
-use substring::Substring;
+use std::collections::HashMap;
 
 pub struct Scanner {
     pub source: String,
+    /// The source decoded once up front: each character with its byte
+    /// offset. `start` and `current` index into this.
+    chars: Vec<(usize, char)>,
     start: usize,
     current: usize,
     line: usize,
+    /// Index of the first character on the current line.
+    line_start: usize,
+    /// Line and column where the token being scanned began.
+    start_line: usize,
+    start_column: usize,
     tokens: Vec<Token>,
+    errors: Vec<Diagnostic>,
     keywords: HashMap<String, TokenType>,
 }
 
@@ -37,10 +45,15 @@ impl Scanner {
 
         Self {
             source: source.to_string(),
+            chars: source.char_indices().collect(),
             tokens: vec![],
+            errors: vec![],
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
             keywords,
         }
     }
@@ -48,22 +61,31 @@ impl Scanner {
     pub fn scan_tokens(&mut self) -> Vec<Token> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.current - self.line_start + 1;
             self.scan_token();
         }
+        let end = self.source.len();
         self.tokens.push(Token {
             tty: crate::token_type::TokenType::Eof,
             lexeme: "".to_string(),
             literal: None,
-            line: 1,
+            span: Span {
+                start: end,
+                end,
+                line: self.line as u64,
+                column: (self.current - self.line_start + 1) as u64,
+            },
         });
-        let mut t = vec![];
-        for token in &self.tokens {
-            t.push(token.clone())
-        }
-        t
+        std::mem::take(&mut self.tokens)
     }
+    /// Problems found by the last call to `scan_tokens`.
+    pub fn errors(&self) -> &[Diagnostic] {
+        &self.errors
+    }
+
     fn is_at_end(&self) -> bool {
-        self.current >= self.source.len()
+        self.current >= self.chars.len()
     }
 
     fn scan_token(&mut self) {
@@ -95,36 +117,34 @@ impl Scanner {
                 true => self.add_token(TokenType::GreaterEqual),
                 false => self.add_token(TokenType::Greater),
             },
-            '/' => match self.check_match('/') {
-                true => {
+            '/' => {
+                if self.check_match('/') {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                } else if self.check_match('*') {
+                    self.block_comment();
+                } else {
+                    self.add_token(TokenType::Slash);
                 }
-                false => self.add_token(TokenType::Slash),
-            },
+            }
             ' ' | '\r' | '\t' => (),
-            '\n' => self.line += 1,
+            '\n' => self.newline(),
             '"' => self.string(),
-            'o' => {
-                if self.check_match('r') {
-                    self.add_token(TokenType::Or)
-                }
-            }
             _ => {
                 if self.is_digit(&c) {
                     self.number();
                 } else if self.is_alpha(&c) {
                     self.identifier();
                 } else {
-                    Lox::error(self.line as u64, "Unexpected character")
+                    self.error("Unexpected character.")
                 }
             }
         }
     }
 
     fn advance(&mut self) -> char {
-        let b = self.source.chars().collect::<Vec<_>>()[self.current];
+        let b = self.chars[self.current].1;
         self.current += 1;
         b
     }
@@ -133,20 +153,64 @@ impl Scanner {
         self.add_token2(tty, None);
     }
     fn add_token2(&mut self, tty: TokenType, literal: Option<String>) {
-        let text = self.source.substring(self.start, self.current);
+        let text = self.text(self.start, self.current).to_string();
         self.tokens.push(Token {
             tty,
-            lexeme: text.to_string(),
+            lexeme: text,
             literal,
-            line: self.line as u64,
+            span: self.span(),
         });
     }
+
+    fn error(&mut self, message: &str) {
+        self.errors.push(Diagnostic::error(message, self.span()));
+    }
+
+    /// Reports an error covering the characters from index `from`, on the
+    /// current line, up to the current character.
+    fn error_on_line(&mut self, from: usize, message: &str) {
+        let span = Span {
+            start: self.byte_offset(from),
+            end: self.byte_offset(self.current),
+            line: self.line as u64,
+            column: (from - self.line_start + 1) as u64,
+        };
+        self.errors.push(Diagnostic::error(message, span));
+    }
+
+    /// Span of the token being scanned.
+    fn span(&self) -> Span {
+        Span {
+            start: self.byte_offset(self.start),
+            end: self.byte_offset(self.current),
+            line: self.start_line as u64,
+            column: self.start_column as u64,
+        }
+    }
+
+    /// Byte offset of the character at index `index`.
+    fn byte_offset(&self, index: usize) -> usize {
+        self.chars
+            .get(index)
+            .map_or(self.source.len(), |(offset, _)| *offset)
+    }
+
+    /// Source text of the characters from index `from` up to `to`.
+    fn text(&self, from: usize, to: usize) -> &str {
+        &self.source[self.byte_offset(from)..self.byte_offset(to)]
+    }
+
+    /// Call after consuming a '\n'.
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
     // match in jlox
     fn check_match(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source.chars().collect::<Vec<_>>()[self.current] != expected {
+        if self.chars[self.current].1 != expected {
             return false;
         }
         self.current += 1;
@@ -157,22 +221,115 @@ impl Scanner {
         if self.is_at_end() {
             return '\0';
         }
-        self.source.chars().collect::<Vec<_>>()[self.current]
+        self.chars[self.current].1
     }
     fn string(&mut self) {
+        let mut value = String::new();
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            match self.advance() {
+                '\\' => {
+                    if let Some(c) = self.escape() {
+                        value.push(c);
+                    }
+                }
+                '\n' => {
+                    self.newline();
+                    value.push('\n');
+                }
+                c => value.push(c),
             }
-            self.advance();
         }
         if self.is_at_end() {
-            Lox::error(self.line as u64, "Unterminated string");
+            let quote = Span {
+                end: self.byte_offset(self.start + 1),
+                ..self.span()
+            };
+            let error = Diagnostic::error("Unterminated string.", quote)
+                .with_label("string starts here");
+            self.errors.push(error);
+            return;
         }
+        // The closing quote.
         self.advance();
-        let value = self.source.substring(self.start - 1, self.current - 1).to_owned();
         self.add_token2(TokenType::String, Some(value));
     }
+
+    /// Skips a `/* ... */` comment whose opening `/*` was just consumed.
+    /// Comments nest, so each `/*` inside needs its own `*/`.
+    fn block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                let opening = Span {
+                    end: self.byte_offset(self.start + 2),
+                    ..self.span()
+                };
+                let error = Diagnostic::error("Unterminated block comment.", opening)
+                    .with_label("comment starts here");
+                self.errors.push(error);
+                return;
+            }
+            match self.advance() {
+                '/' if self.check_match('*') => depth += 1,
+                '*' if self.check_match('/') => depth -= 1,
+                '\n' => self.newline(),
+                _ => (),
+            }
+        }
+    }
+
+    /// Decodes the escape sequence after a backslash that was just consumed.
+    /// Reports the error and returns `None` if it is not valid.
+    fn escape(&mut self) -> Option<char> {
+        let backslash = self.current - 1;
+        let c = match self.peek() {
+            // Leave these for `string` to deal with.
+            '\n' => {
+                self.error_on_line(backslash, "Invalid escape sequence.");
+                return None;
+            }
+            _ if self.is_at_end() => return None,
+            _ => self.advance(),
+        };
+        match c {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            'u' => self.unicode_escape(backslash),
+            _ => {
+                self.error_on_line(backslash, &format!("Invalid escape sequence '\\{}'.", c));
+                None
+            }
+        }
+    }
+
+    /// Decodes the `{XXXX}` part of a `\u{XXXX}` escape: one to six hex
+    /// digits naming a Unicode scalar value.
+    fn unicode_escape(&mut self, backslash: usize) -> Option<char> {
+        if !self.check_match('{') {
+            self.error_on_line(backslash, "Expect '{' after '\\u'.");
+            return None;
+        }
+        let digits_start = self.current;
+        while self.peek().is_ascii_hexdigit() {
+            self.advance();
+        }
+        let digits = self.text(digits_start, self.current);
+        let code = if (1..=6).contains(&digits.len()) {
+            u32::from_str_radix(digits, 16).ok().and_then(char::from_u32)
+        } else {
+            None
+        };
+        if !self.check_match('}') {
+            self.error_on_line(backslash, "Expect '}' after unicode escape digits.");
+            return None;
+        }
+        if code.is_none() {
+            self.error_on_line(backslash, "Invalid unicode escape.");
+        }
+        code
+    }
     fn is_digit(&self, c: &char) -> bool {
         (&'0'..=&'9').contains(&c)
     }
@@ -188,28 +345,26 @@ impl Scanner {
                 self.advance();
             }
         }
-        let value = self.source.substring(self.start, self.current).to_string();
-        let iv: u64 = value.parse().unwrap();
-        self.add_token2(TokenType::Number, Some(format!("{}", iv)))
+        match self.text(self.start, self.current).parse::<f64>() {
+            Ok(value) => self.add_token2(TokenType::Number, Some(value.to_string())),
+            Err(_) => self.error("Invalid number literal."),
+        }
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 > self.source.len() {
-            return '\0';
-        }
-        self.source.chars().collect::<Vec<_>>()[self.current + 1]
+        self.chars.get(self.current + 1).map_or('\0', |(_, c)| *c)
     }
     fn identifier(&mut self) {
         while self.is_alpha_numeric(&self.peek()) {
             self.advance();
         }
-        let text = self.source.substring(self.start, self.current);
+        let text = self.text(self.start, self.current);
         let tty = self.keywords.get(text);
         match tty {
             Some(t) => {
                 let t = t.clone();
                 self.add_token(t)
-            },
+            }
             None => self.add_token(TokenType::Indentifier),
         }
     }
@@ -221,3 +376,117 @@ impl Scanner {
         self.is_alpha(c) || self.is_digit(c)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(source: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        (tokens, scanner.errors().to_vec())
+    }
+
+    fn span(start: usize, end: usize, line: u64, column: u64) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn columns_count_characters_and_offsets_count_bytes() {
+        let (tokens, errors) = scan("\"héllo\" + x\n  y");
+        assert!(errors.is_empty());
+        let spans: Vec<Span> = tokens.iter().map(|token| token.span).collect();
+        assert_eq!(
+            spans,
+            [
+                span(0, 8, 1, 1),
+                span(9, 10, 1, 9),
+                span(11, 12, 1, 11),
+                span(15, 16, 2, 3),
+                span(16, 16, 2, 4),
+            ]
+        );
+        assert_eq!(tokens[0].literal.as_deref(), Some("héllo"));
+    }
+
+    #[test]
+    fn unexpected_non_ascii_character() {
+        let (tokens, errors) = scan("a ☃ b");
+        let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+        assert_eq!(lexemes, ["a", "b", ""]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Unexpected character.");
+        assert_eq!(errors[0].primary.span, span(2, 5, 1, 3));
+    }
+
+    #[test]
+    fn string_literals_drop_their_quotes() {
+        let (tokens, errors) = scan(r#"print "abc";"#);
+        assert!(errors.is_empty());
+        assert_eq!(tokens[1].lexeme, r#""abc""#);
+        assert_eq!(tokens[1].literal.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let (tokens, errors) = scan(r#""a\nb\t\"c\"\\ \u{48}\u{1F600}""#);
+        assert!(errors.is_empty());
+        assert_eq!(tokens[0].literal.as_deref(), Some("a\nb\t\"c\"\\ H😀"));
+    }
+
+    #[test]
+    fn invalid_escapes_point_at_the_escape() {
+        let (tokens, errors) = scan("x = \"a\\qb\";\n\"\\u{110000}\" \"\\u41\"");
+        let reported: Vec<(&str, Span)> = errors
+            .iter()
+            .map(|error| (error.message.as_str(), error.primary.span))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                ("Invalid escape sequence '\\q'.", span(6, 8, 1, 7)),
+                ("Invalid unicode escape.", span(13, 23, 2, 2)),
+                ("Expect '{' after '\\u'.", span(26, 28, 2, 15)),
+            ]
+        );
+        // The rest of the string is still scanned.
+        assert_eq!(tokens[2].literal.as_deref(), Some("ab"));
+    }
+
+    #[test]
+    fn unterminated_string_points_at_its_opening_quote() {
+        let (tokens, errors) = scan("print \"abc\ndef");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Unterminated string.");
+        assert_eq!(errors[0].primary.span, span(6, 7, 1, 7));
+        assert_eq!(errors[0].primary.message, "string starts here");
+        let types: Vec<TokenType> = tokens.iter().map(|token| token.tty.clone()).collect();
+        assert_eq!(types, [TokenType::Print, TokenType::Eof]);
+        assert_eq!(tokens[1].span.line, 2);
+    }
+
+    #[test]
+    fn block_comments_nest_and_count_lines() {
+        let (tokens, errors) = scan("a /* one /* two\n */ still\n */ b / c");
+        assert!(errors.is_empty());
+        let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+        assert_eq!(lexemes, ["a", "b", "/", "c", ""]);
+        assert_eq!(tokens[1].span, span(30, 31, 3, 5));
+    }
+
+    #[test]
+    fn unterminated_block_comment_points_at_its_opening() {
+        let (tokens, errors) = scan("a\n  /* /* */\nb");
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Unterminated block comment.");
+        assert_eq!(errors[0].primary.span, span(4, 6, 2, 3));
+        assert_eq!(errors[0].primary.message, "comment starts here");
+    }
+}
+
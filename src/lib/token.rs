@@ -1,17 +1,41 @@
 use crate::token_type::TokenType;
-use std::{string::ToString};
-pub trait Object { }
+use std::fmt;
+pub trait Object {}
+
+/// A region of source text. `start` and `end` are byte offsets (`end` is
+/// exclusive); `line` and `column` are 1-based and mark where it begins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u64,
+    pub column: u64,
+}
+
+impl Span {
+    /// The smallest span covering both `self` and a later span `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: self.end.max(other.end),
+            ..self
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Token {
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
     pub tty: TokenType,
     pub lexeme: String,
     pub literal: Option<String>,
-    pub line: u64
+    pub span: Span,
 }
 
-
-impl ToString for Token {
-    fn to_string(&self) -> String {
-        format!("{}  {} {}", &self.tty, &self.lexeme, &self.literal.as_ref().unwrap())
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let literal = self.literal.as_deref().unwrap_or("null");
+        write!(f, "{}  {} {}", &self.tty, &self.lexeme, literal)
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,6 @@
 use strum::Display;
 #[derive(Debug, Display, Clone, PartialEq, Eq)]
-
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum TokenType {
     LeftParen,
     RightParen,
@@ -21,6 +21,7 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    #[cfg_attr(feature = "serde", serde(rename = "Identifier"))]
     Indentifier,
     String,
     Number,
@@ -0,0 +1,93 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    callable::LoxCallable,
+    class::{LoxClass, LoxInstance},
+    natives::NativeFunction,
+    vm::Object,
+};
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Callable(Rc<dyn LoxCallable>),
+    /// Shared by both backends, so a native defined once works in either.
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    /// Functions, classes and instances of the bytecode VM.
+    Vm(Object),
+}
+
+impl Value {
+    // false and nil are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Bool(b) => *b,
+            _ => true,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            // Callables, classes and instances compare by identity.
+            (Value::Callable(a), Value::Callable(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::Vm(a), Value::Vm(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Literal values map onto their JSON counterparts; functions, classes and
+/// instances, which only exist at runtime, serialize as their display form.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Number(n) => serializer.serialize_f64(*n),
+            Value::String(s) => serializer.serialize_str(s),
+            value => serializer.collect_str(value),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => fmt_number(*n, f),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Callable(c) => write!(f, "{}", c),
+            Value::Native(n) => write!(f, "{}", n),
+            Value::Class(c) => write!(f, "{}", c),
+            Value::Instance(i) => write!(f, "{}", i.borrow()),
+            Value::Vm(o) => write!(f, "{}", o),
+        }
+    }
+}
+
+/// Integral numbers print without a fractional part, e.g. `3` not `3.0`.
+fn fmt_number(n: f64, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if n.is_infinite() {
+        write!(f, "{}Infinity", if n < 0.0 { "-" } else { "" })
+    } else {
+        // Rust already omits the fraction of integral floats.
+        write!(f, "{}", n)
+    }
+}
@@ -0,0 +1,596 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{
+    callable::{LoxCallable, MAX_CALL_DEPTH},
+    chunk::{Function, OpCode},
+    errors::VmError,
+    natives,
+    value::Value,
+};
+
+/// The VM's own functions, classes and instances, which Lox code sees as
+/// [`Value::Vm`].
+#[derive(Debug, Clone)]
+pub enum Object {
+    /// Only found in constant pools, until a `Closure` instruction wraps it.
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Object::Function(a), Object::Function(b)) => Rc::ptr_eq(a, b),
+            (Object::Closure(a), Object::Closure(b)) => Rc::ptr_eq(a, b),
+            (Object::BoundMethod(a), Object::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            (Object::Class(a), Object::Class(b)) => Rc::ptr_eq(a, b),
+            (Object::Instance(a), Object::Instance(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Function(function) => write!(f, "{}", function),
+            Object::Closure(closure) => write!(f, "{}", closure.function),
+            Object::BoundMethod(bound) => write!(f, "{}", bound.method.function),
+            Object::Class(class) => write!(f, "{}", class.name),
+            Object::Instance(instance) => write!(f, "{} instance", instance.class.name),
+        }
+    }
+}
+
+/// A variable captured by a closure. It points into the stack while the
+/// variable is in scope, and holds the value itself once it is not.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A method together with the instance it was looked up on.
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    /// Includes inherited methods, which are copied in when the class is
+    /// created, so lookups never walk the superclass chain.
+    methods: RefCell<HashMap<String, Rc<Closure>>>,
+}
+
+impl Class {
+    fn find_method(&self, name: &str) -> Option<Rc<Closure>> {
+        self.methods.borrow().get(name).cloned()
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    fields: RefCell<HashMap<String, Value>>,
+}
+
+/// The function running and where its locals start on the stack.
+#[derive(Debug)]
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    slots: usize,
+}
+
+/// Runs functions compiled by the [`Compiler`](crate::compiler::Compiler).
+/// Globals persist from one call of [`Vm::interpret`] to the next.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<String, Value>,
+    /// Upvalues still pointing into the stack, ordered by slot.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Receives `Print` instructions' output; stdout unless redirected.
+    output: Box<dyn Write>,
+}
+
+impl fmt::Debug for Vm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vm")
+            .field("stack", &self.stack)
+            .field("frames", &self.frames)
+            .field("globals", &self.globals)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Self {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            output: Box::new(io::stdout()),
+        };
+        vm.define_builtins();
+        vm
+    }
+
+    /// Redirects `print` output. [`Lox::set_output`](crate::lox::Lox::set_output)
+    /// does this for both backends at once.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Clears the globals back to just the builtins.
+    pub fn reset(&mut self) {
+        self.globals.clear();
+        self.define_builtins();
+    }
+
+    fn define_builtins(&mut self) {
+        for native in natives::builtins() {
+            self.define_global(native.name().to_string(), Value::Native(native));
+        }
+    }
+
+    /// Global names and values, in no particular order.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.globals.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Binds `name` as a top-level `var` declaration would.
+    pub fn define_global(&mut self, name: String, value: Value) {
+        self.globals.insert(name, value);
+    }
+
+    /// Runs a compiled script.
+    pub fn interpret(&mut self, script: Rc<Function>) -> Result<(), VmError> {
+        let closure = Rc::new(Closure {
+            function: script,
+            upvalues: Vec::new(),
+        });
+        self.stack.push(Value::Vm(Object::Closure(closure.clone())));
+        let result = self.call(closure, 0).and_then(|()| self.run());
+        if result.is_err() {
+            // Closures that outlive the failed run keep the values they saw.
+            self.close_upvalues(0);
+            self.stack.clear();
+            self.frames.clear();
+        }
+        result
+    }
+
+    fn run(&mut self) -> Result<(), VmError> {
+        // Kept outside the frame so constants can be read while the stack
+        // changes. Calls and returns switch it.
+        let mut function = self.function();
+        loop {
+            let frame = self.frames.last_mut().expect("a function is running");
+            let op = function.chunk.code[frame.ip];
+            frame.ip += 1;
+            let slots = frame.slots;
+            match op {
+                OpCode::Constant(constant) => {
+                    let value = function.chunk.constants[constant as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[slots + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    self.stack[slots + slot as usize] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal(name) => {
+                    let name = string_constant(&function, name);
+                    match self.globals.get(name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                OpCode::DefineGlobal(name) => {
+                    let name = string_constant(&function, name);
+                    let value = self.pop();
+                    self.globals.insert(name.to_string(), value);
+                }
+                OpCode::SetGlobal(name) => {
+                    let name = string_constant(&function, name);
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                OpCode::GetUpvalue(index) => {
+                    let value = match &*self.upvalue(index).borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let value = self.peek(0).clone();
+                    match &mut *self.upvalue(index).borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty(name) => {
+                    let name = string_constant(&function, name);
+                    let Value::Vm(Object::Instance(instance)) = self.peek(0).clone() else {
+                        return Err(self.error("Only instances have properties."));
+                    };
+                    let field = instance.fields.borrow().get(name).cloned();
+                    let value = match field {
+                        Some(value) => value,
+                        // Only then is it a method, bound to the instance.
+                        None => match instance.class.find_method(name) {
+                            Some(method) => {
+                                bound_method(Value::Vm(Object::Instance(instance)), method)
+                            }
+                            None => return Err(self.undefined_property(name)),
+                        },
+                    };
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::ExpectInstance => {
+                    if !matches!(self.peek(0), Value::Vm(Object::Instance(_))) {
+                        return Err(self.error("Only instances have fields."));
+                    }
+                }
+                OpCode::SetProperty(name) => {
+                    let name = string_constant(&function, name);
+                    let value = self.pop();
+                    let Value::Vm(Object::Instance(instance)) = self.pop() else {
+                        return Err(self.error("Only instances have fields."));
+                    };
+                    instance
+                        .fields
+                        .borrow_mut()
+                        .insert(name.to_string(), value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper(name) => {
+                    let name = string_constant(&function, name);
+                    let superclass = self.pop();
+                    let receiver = self.pop();
+                    let method = match &superclass {
+                        Value::Vm(Object::Class(class)) => class.find_method(name),
+                        _ => None,
+                    };
+                    match method {
+                        Some(method) => self.stack.push(bound_method(receiver, method)),
+                        None => return Err(self.undefined_property(name)),
+                    }
+                }
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Bool(left == right));
+                }
+                OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Bool(left != right));
+                }
+                OpCode::Greater => {
+                    let (l, r) = self.number_operands()?;
+                    self.stack.push(Value::Bool(l > r));
+                }
+                OpCode::GreaterEqual => {
+                    let (l, r) = self.number_operands()?;
+                    self.stack.push(Value::Bool(l >= r));
+                }
+                OpCode::Less => {
+                    let (l, r) = self.number_operands()?;
+                    self.stack.push(Value::Bool(l < r));
+                }
+                OpCode::LessEqual => {
+                    let (l, r) = self.number_operands()?;
+                    self.stack.push(Value::Bool(l <= r));
+                }
+                OpCode::Add => {
+                    let value = match (self.peek(1), self.peek(0)) {
+                        (Value::Number(l), Value::Number(r)) => Value::Number(l + r),
+                        (Value::String(l), Value::String(r)) => {
+                            Value::String(format!("{}{}", l, r))
+                        }
+                        _ => return Err(self.error("Operands must be two numbers or two strings.")),
+                    };
+                    self.pop();
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::Subtract => {
+                    let (l, r) = self.number_operands()?;
+                    self.stack.push(Value::Number(l - r));
+                }
+                OpCode::Multiply => {
+                    let (l, r) = self.number_operands()?;
+                    self.stack.push(Value::Number(l * r));
+                }
+                OpCode::Divide => {
+                    let (l, r) = self.number_operands()?;
+                    self.stack.push(Value::Number(l / r));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.peek(0) {
+                    Value::Number(n) => {
+                        let n = -n;
+                        self.pop();
+                        self.stack.push(Value::Number(n));
+                    }
+                    _ => return Err(self.error("Operand must be a number.")),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    let _ = self.output.write_all(format!("{}\n", value).as_bytes());
+                }
+                OpCode::Jump(distance) => self.frame_mut().ip += distance as usize,
+                OpCode::JumpIfFalse(distance) => {
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += distance as usize;
+                    }
+                }
+                OpCode::Loop(distance) => self.frame_mut().ip -= distance as usize,
+                OpCode::Call(count) => {
+                    let callee = self.peek(count as usize).clone();
+                    self.call_value(callee, count as usize)?;
+                    function = self.function();
+                }
+                OpCode::Closure(constant) => {
+                    let Value::Vm(Object::Function(function)) =
+                        function.chunk.constants[constant as usize].clone()
+                    else {
+                        unreachable!("closure constants are always functions");
+                    };
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| {
+                            if upvalue.is_local {
+                                self.capture_upvalue(slots + upvalue.index as usize)
+                            } else {
+                                self.upvalue(upvalue.index)
+                            }
+                        })
+                        .collect();
+                    let closure = Closure { function, upvalues };
+                    self.stack.push(Value::Vm(Object::Closure(Rc::new(closure))));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    self.close_upvalues(slots);
+                    self.frames.pop();
+                    self.stack.truncate(slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                    function = self.function();
+                }
+                OpCode::Class(name) => {
+                    let class = Class {
+                        name: string_constant(&function, name).to_string(),
+                        methods: RefCell::default(),
+                    };
+                    self.stack.push(Value::Vm(Object::Class(Rc::new(class))));
+                }
+                OpCode::Subclass(name) => {
+                    let Value::Vm(Object::Class(superclass)) = self.pop() else {
+                        return Err(self.error("Superclass must be a class."));
+                    };
+                    let class = Class {
+                        name: string_constant(&function, name).to_string(),
+                        methods: superclass.methods.clone(),
+                    };
+                    self.stack.push(Value::Vm(Object::Class(Rc::new(class))));
+                }
+                OpCode::Method(name) => {
+                    let name = string_constant(&function, name);
+                    let Value::Vm(Object::Closure(method)) = self.pop() else {
+                        unreachable!("methods are always closures");
+                    };
+                    if let Value::Vm(Object::Class(class)) = self.peek(0) {
+                        class.methods.borrow_mut().insert(name.to_string(), method);
+                    }
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, count: usize) -> Result<(), VmError> {
+        let receiver_slot = self.stack.len() - count - 1;
+        match callee {
+            Value::Vm(Object::Closure(closure)) => self.call(closure, count),
+            Value::Vm(Object::BoundMethod(bound)) => {
+                self.stack[receiver_slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), count)
+            }
+            Value::Vm(Object::Class(class)) => {
+                let instance = Instance {
+                    class: class.clone(),
+                    fields: RefCell::default(),
+                };
+                self.stack[receiver_slot] = Value::Vm(Object::Instance(Rc::new(instance)));
+                // The instance takes the class's slot, becoming `this` for
+                // `init`, whose arguments are already in place.
+                match class.find_method("init") {
+                    Some(initializer) => self.call(initializer, count),
+                    None if count != 0 => Err(self.arity_error(0, count)),
+                    None => Ok(()),
+                }
+            }
+            Value::Native(native) => {
+                if count != native.arity() {
+                    return Err(self.arity_error(native.arity(), count));
+                }
+                match native.invoke(&self.stack[receiver_slot + 1..]) {
+                    Ok(value) => {
+                        self.stack.truncate(receiver_slot);
+                        self.stack.push(value);
+                        Ok(())
+                    }
+                    Err(message) => Err(self.error(&message)),
+                }
+            }
+            _ => Err(self.error("Can only call functions and classes.")),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, count: usize) -> Result<(), VmError> {
+        if count != closure.function.arity {
+            return Err(self.arity_error(closure.function.arity, count));
+        }
+        // The script's own frame does not count.
+        if self.frames.len() > MAX_CALL_DEPTH {
+            return Err(self.error("Stack overflow."));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - count - 1,
+        });
+        Ok(())
+    }
+
+    /// Finds or creates the upvalue for the stack slot `slot`, so closures
+    /// capturing the same variable share it.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .partition_point(|upvalue| open_slot(upvalue) < slot);
+        if let Some(upvalue) = self.open_upvalues.get(position) {
+            if open_slot(upvalue) == slot {
+                return upvalue.clone();
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.insert(position, upvalue.clone());
+        upvalue
+    }
+
+    /// Moves every variable at or above `slot` off the stack and into the
+    /// upvalues that capture it.
+    fn close_upvalues(&mut self, slot: usize) {
+        while let Some(upvalue) = self
+            .open_upvalues
+            .pop_if(|upvalue| open_slot(upvalue) >= slot)
+        {
+            let value = self.stack[open_slot(&upvalue)].clone();
+            *upvalue.borrow_mut() = Upvalue::Closed(value);
+        }
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("a function is running")
+    }
+
+    /// The function the innermost frame is running.
+    fn function(&self) -> Rc<Function> {
+        let frame = self.frames.last().expect("a function is running");
+        frame.closure.function.clone()
+    }
+
+    fn upvalue(&self, index: u32) -> Rc<RefCell<Upvalue>> {
+        let frame = self.frames.last().expect("a function is running");
+        frame.closure.upvalues[index as usize].clone()
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("compiler balances the stack")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn number_operands(&mut self) -> Result<(f64, f64), VmError> {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(l), Value::Number(r)) => {
+                let operands = (*l, *r);
+                self.pop();
+                self.pop();
+                Ok(operands)
+            }
+            _ => Err(self.error("Operands must be numbers.")),
+        }
+    }
+
+    /// A runtime error at the instruction that just ran.
+    fn error(&self, message: &str) -> VmError {
+        let frame = self.frames.last().expect("a function is running");
+        let span = frame.closure.function.chunk.span_at(frame.ip - 1);
+        VmError::new(span, message)
+    }
+
+    fn arity_error(&self, arity: usize, count: usize) -> VmError {
+        self.error(&format!("Expected {} arguments but got {}.", arity, count))
+    }
+
+    fn undefined_variable(&self, name: &str) -> VmError {
+        self.error(&format!("Undefined variable '{}'.", name))
+    }
+
+    fn undefined_property(&self, name: &str) -> VmError {
+        self.error(&format!("Undefined property '{}'.", name))
+    }
+}
+
+fn open_slot(upvalue: &Rc<RefCell<Upvalue>>) -> usize {
+    match &*upvalue.borrow() {
+        Upvalue::Open(slot) => *slot,
+        Upvalue::Closed(_) => unreachable!("closed upvalues are not tracked"),
+    }
+}
+
+fn string_constant(function: &Function, index: u32) -> &str {
+    match &function.chunk.constants[index as usize] {
+        Value::String(string) => string,
+        _ => unreachable!("names are always string constants"),
+    }
+}
+
+fn bound_method(receiver: Value, method: Rc<Closure>) -> Value {
+    Value::Vm(Object::BoundMethod(Rc::new(BoundMethod { receiver, method })))
+}
@@ -1,10 +1,6 @@
-use rlox::{
-    scanner::Scanner, parser::Parser, interpreter::Interpreter
-};
+use rlox::lox::Lox;
 fn main() {
-    let mut s = Scanner::new("var a;");
-    let _tokens = s.scan_tokens();
-    let mut parser = Parser::new(_tokens);
-    let a = parser.parse();
-    let value = Interpreter::new().interpret(a);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = Lox::new().run_args(&args);
+    std::process::exit(code);
 }
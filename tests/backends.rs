@@ -0,0 +1,297 @@
+//! Runs the same programs on the tree-walking interpreter and on the
+//! bytecode VM, which must agree on everything a program can observe.
+
+use std::{cell::RefCell, rc::Rc};
+
+use rlox::{
+    callable::MAX_CALL_DEPTH,
+    lox::{Backend, Lox},
+    value::Value,
+};
+
+/// What a program did: everything it printed, then the runtime error it
+/// stopped with, as the message and line.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    output: String,
+    error: Option<(String, u64)>,
+}
+
+fn run(backend: Backend, source: &str) -> Outcome {
+    let output = Rc::new(RefCell::new(String::new()));
+    let error = Rc::new(RefCell::new(None));
+    let mut lox = Lox::new();
+    lox.set_backend(backend);
+    let sink = output.clone();
+    lox.set_output_fn(move |line| sink.borrow_mut().push_str(line));
+    let errors = error.clone();
+    lox.set_diagnostic_handler(move |diagnostic| {
+        *errors.borrow_mut() = Some((diagnostic.message.clone(), diagnostic.primary.span.line));
+    });
+    lox.run(source);
+    assert!(
+        !lox.had_error(),
+        "{:?} did not compile: {:?}",
+        source,
+        error.borrow()
+    );
+    let output = output.take();
+    let error = error.take();
+    Outcome { output, error }
+}
+
+/// Runs `source` on both backends, checks they agree and returns what the
+/// interpreter did.
+fn both(source: &str) -> Outcome {
+    let interpreter = run(Backend::Interpreter, source);
+    let vm = run(Backend::Vm, source);
+    assert_eq!(interpreter, vm, "backends disagree on {:?}", source);
+    interpreter
+}
+
+fn prints(source: &str, expected: &str) {
+    assert_eq!(
+        both(source),
+        Outcome {
+            output: expected.to_string(),
+            error: None
+        }
+    );
+}
+
+fn fails(source: &str, message: &str, line: u64) {
+    assert_eq!(both(source).error, Some((message.to_string(), line)));
+}
+
+#[test]
+fn expressions() {
+    prints("print 1 + 2 * 3 - 4 / 8;", "6.5\n");
+    prints("print \"a\" + \"b\";", "ab\n");
+    prints(
+        "print 1 / 0; print -1 / 0; print 0 / 0 == 0 / 0;",
+        "Infinity\n-Infinity\nfalse\n",
+    );
+    prints(
+        "print 0.1 + 0.2; print 3.0; print -0;",
+        "0.30000000000000004\n3\n-0\n",
+    );
+    prints("print nil; print !nil; print -(3);", "nil\ntrue\n-3\n");
+    prints(
+        "print true and nil; print false or \"x\"; print nil or false;",
+        "nil\nx\nfalse\n",
+    );
+    prints(
+        "print 1 < 2; print 2 <= 2; print 3 > 4; print 3 >= 3; print 1 == 1; print \"a\" != \"a\";",
+        "true\ntrue\nfalse\ntrue\ntrue\nfalse\n",
+    );
+}
+
+#[test]
+fn variables_and_control_flow() {
+    prints("var g = 10; g = g + 1; print g;", "11\n");
+    prints(
+        "{ var a = 1; { var b = a + 1; print b; a = 5; } print a; }",
+        "2\n5\n",
+    );
+    prints(
+        "var i = 0; while (i < 3) { print i; i = i + 1; }",
+        "0\n1\n2\n",
+    );
+    prints(
+        "for (var j = 0; j < 3; j = j + 1) print j * 10;",
+        "0\n10\n20\n",
+    );
+    prints("if (nil) print \"no\"; else print \"else\";", "else\n");
+    prints(
+        "var s = \"x\"; for (var q = 0; q < 3; q = q + 1) s = s + s; print s;",
+        "xxxxxxxx\n",
+    );
+}
+
+#[test]
+fn functions() {
+    prints(
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(20);",
+        "6765\n",
+    );
+    prints(
+        "fun f() {} print f; print f(); print f == f;",
+        "<fn f>\nnil\ntrue\n",
+    );
+    prints(
+        "print clock; print clock() > 0;",
+        "<native fn clock>\ntrue\n",
+    );
+    prints(
+        "fun early() { while (true) { return \"out\"; } } print early();",
+        "out\n",
+    );
+}
+
+#[test]
+fn closures() {
+    prints(
+        "fun makeCounter() { var c = 0; fun inc() { c = c + 1; return c; } return inc; }
+         var c1 = makeCounter(); var c2 = makeCounter();
+         print c1(); print c1(); print c2();",
+        "1\n2\n1\n",
+    );
+    prints(
+        "fun outer() { var x = \"outer\"; fun mid() { fun inner() { return x; } return inner; } return mid()(); }
+         print outer();",
+        "outer\n",
+    );
+    prints(
+        "var get = nil;
+         { var shared = 1; fun g() { return shared; } fun set(v) { shared = v; } set(42); get = g; }
+         print get();",
+        "42\n",
+    );
+    prints(
+        "var f = nil;
+         for (var k = 0; k < 3; k = k + 1) { fun g() { return k; } if (k == 1) f = g; }
+         print f();",
+        "3\n",
+    );
+}
+
+#[test]
+fn classes() {
+    prints(
+        "class Animal {
+           init(name) { this.name = name; }
+           speak() { return this.name + \" makes a sound\"; }
+         }
+         class Dog < Animal {
+           speak() { return super.speak() + \" (woof)\"; }
+         }
+         var d = Dog(\"Rex\");
+         print d.speak(); print d; print Dog; print d.speak;
+         var m = d.speak; d.name = \"Max\"; print m();
+         print d.init(\"Bob\") == d; print d.name;",
+        "Rex makes a sound (woof)\nDog instance\nDog\n<fn speak>\n\
+         Max makes a sound (woof)\ntrue\nBob\n",
+    );
+    prints(
+        "class A { method() { return \"A\"; } }
+         class B < A { method() { return \"B\"; } test() { return super.method(); } }
+         class C < B {}
+         print C().test();",
+        "A\n",
+    );
+    prints(
+        "class F { get() { fun inner() { return this.v; } return inner; } }
+         var f = F(); f.v = \"captured\"; print f.get()();",
+        "captured\n",
+    );
+    prints(
+        "class Field { init() { this.m = \"field\"; } m() { return \"method\"; } }
+         print Field().m;",
+        "field\n",
+    );
+}
+
+#[test]
+fn runtime_errors() {
+    fails(
+        "print 1 + \"a\";",
+        "Operands must be two numbers or two strings.",
+        1,
+    );
+    fails("print -\"a\";", "Operand must be a number.", 1);
+    fails("print 1 < \"a\";", "Operands must be numbers.", 1);
+    fails(
+        "print undefinedVar;",
+        "Undefined variable 'undefinedVar'.",
+        1,
+    );
+    fails("undefinedVar = 3;", "Undefined variable 'undefinedVar'.", 1);
+    fails("\"notfn\"();", "Can only call functions and classes.", 1);
+    fails(
+        "fun f(a) {}\nf(1, 2);",
+        "Expected 1 arguments but got 2.",
+        2,
+    );
+    fails("class K {}\nK(1);", "Expected 0 arguments but got 1.", 2);
+    fails(
+        "class K { init(a, b) {} }\nK(1);",
+        "Expected 2 arguments but got 1.",
+        2,
+    );
+    fails("print clock(1);", "Expected 0 arguments but got 1.", 1);
+    fails(
+        "var x = 1; print x.y;",
+        "Only instances have properties.",
+        1,
+    );
+    fails("var x = 1; x.y = 2;", "Only instances have fields.", 1);
+    fails(
+        "class K {} print K().nope;",
+        "Undefined property 'nope'.",
+        1,
+    );
+    fails(
+        "var NotClass = 1;\nclass K < NotClass {}",
+        "Superclass must be a class.",
+        2,
+    );
+    fails(
+        "class A {}\nclass B < A { m() { return super.nope; } }\nB().m();",
+        "Undefined property 'nope'.",
+        2,
+    );
+    fails(
+        "class P {\n  m() { return this.missing; }\n}\nP().m();",
+        "Undefined property 'missing'.",
+        2,
+    );
+}
+
+#[test]
+fn output_before_an_error_is_kept() {
+    let outcome = both(
+        "print \"before\";
+         { var a = 1; fun g() { return a; } print g(); print 1 - nil; }",
+    );
+    assert_eq!(outcome.output, "before\n1\n");
+    assert_eq!(
+        outcome.error,
+        Some(("Operands must be numbers.".to_string(), 2))
+    );
+}
+
+#[test]
+fn stack_overflow() {
+    fails("fun f(n) { return f(n + 1); }\nf(0);", "Stack overflow.", 1);
+    // d(n) makes n + 1 calls.
+    let outcome = both(&format!(
+        "fun d(n) {{ if (n == 0) return 0; return 1 + d(n - 1); }}
+         print d({}); print d({});",
+        MAX_CALL_DEPTH - 1,
+        MAX_CALL_DEPTH
+    ));
+    assert_eq!(outcome.output, format!("{}\n", MAX_CALL_DEPTH - 1));
+    assert_eq!(
+        outcome.error.map(|(message, _)| message),
+        Some("Stack overflow.".to_string())
+    );
+}
+
+#[test]
+fn natives_defined_on_lox() {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    for backend in [Backend::Interpreter, Backend::Vm] {
+        let mut lox = Lox::new();
+        lox.set_backend(backend);
+        lox.define_native("twice", 1, |arguments| match arguments {
+            [Value::Number(n)] => Ok(Value::Number(n * 2.0)),
+            _ => Err("Expected a number.".to_string()),
+        });
+        let sink = output.clone();
+        lox.set_output_fn(move |line| sink.borrow_mut().push(line.to_string()));
+        lox.run("print twice(21); print twice;");
+        assert!(!lox.had_runtime_error());
+    }
+    let expected = ["42\n", "<native fn twice>\n"];
+    assert_eq!(*output.borrow(), [expected, expected].concat());
+}